@@ -0,0 +1,97 @@
+#[path = "../tests/common/mod.rs"]
+mod common;
+mod reference;
+
+use common::noise;
+use puyomdtool::compress::{CompressMode, compress_with_mode};
+use std::time::{Duration, Instant};
+
+// Something shaped a bit like tile data: 4bpp pixels from a handful of colors, with rows that
+// repeat now and then.
+fn tiles(len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    let pixels = noise(len, 0x1234);
+    while data.len() < len {
+        let row = data.len();
+        if row >= 0x40 && pixels[row] & 0x3 == 0 {
+            data.extend_from_within(row - 0x40..row - 0x3C);
+        } else {
+            data.extend(pixels[row..row + 4].iter().map(|p| p & 0x33));
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+// Runs compress for at least a second to get a stable number, and returns the time each run took
+// and the compressed size.
+fn time(compress: impl Fn() -> Vec<u8>) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut iterations = 0;
+    let mut output_len = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        output_len = compress().len();
+        iterations += 1;
+    }
+    (start.elapsed() / iterations, output_len)
+}
+
+fn mb_per_sec(len: usize, per_iteration: Duration) -> f64 {
+    len as f64 / per_iteration.as_secs_f64() / 1_000_000.0
+}
+
+// Times compress_with_mode, and for greedy mode the old compressor it replaced, which gives the
+// same output.
+fn bench(name: &str, data: &[u8], mode: CompressMode) {
+    let (per_iteration, output_len) = time(|| compress_with_mode(data, mode));
+    let old = match mode {
+        CompressMode::Greedy => {
+            assert_eq!(reference::compress(data), compress_with_mode(data, mode));
+            let (old_per_iteration, _) = time(|| reference::compress(data));
+            format!(
+                "{:>12?}/iter  {:>8.2} MB/s  {:>6.1}x",
+                old_per_iteration,
+                mb_per_sec(data.len(), old_per_iteration),
+                old_per_iteration.as_secs_f64() / per_iteration.as_secs_f64()
+            )
+        }
+        CompressMode::Best => String::new(),
+    };
+
+    let line = format!(
+        "{:<8} {:<7} {:>7} -> {:>7} bytes  {:>12?}/iter  {:>8.2} MB/s  {}",
+        name,
+        format!("{mode:?}"),
+        data.len(),
+        output_len,
+        per_iteration,
+        mb_per_sec(data.len(), per_iteration),
+        old
+    );
+    println!("{}", line.trim_end());
+}
+
+fn main() {
+    let text: Vec<u8> = [
+        include_bytes!("../src/compress.rs").as_slice(),
+        include_bytes!("../src/decompress.rs").as_slice(),
+        include_bytes!("../src/convert.rs").as_slice(),
+    ]
+    .concat();
+    let inputs = [
+        ("zeros", vec![0; 0x10000]),
+        ("noise", noise(0x10000, 1)),
+        ("tiles", tiles(0x10000)),
+        ("text", text),
+    ];
+
+    println!(
+        "{:<8} {:<7} {:>24}  {:>32}  {:>41}",
+        "input", "mode", "size", "hash chains", "old search (speedup)"
+    );
+    for mode in [CompressMode::Greedy, CompressMode::Best] {
+        for (name, data) in inputs.iter() {
+            bench(name, data, mode);
+        }
+    }
+}
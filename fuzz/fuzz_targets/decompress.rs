@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/common/fuzz.rs"]
+mod checks;
+
+fuzz_target!(|data: &[u8]| checks::check_decompress(data));
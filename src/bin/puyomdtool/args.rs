@@ -0,0 +1,839 @@
+// Command line parsing.  Each command is described by a Command, which lists the options it takes
+// and the help text shown for it, and parse turns the raw arguments into Matches for one of them.
+//
+// The tool used to squash options into command names (decompressnobuf, convertwordin, ...) and
+// take everything else as positional arguments.  Those spellings are still understood, and are
+// rewritten into the new form with a warning.
+
+use puyomdtool::PMDTError;
+
+pub struct Opt {
+    pub name: &'static str,
+    // The name of the option's value in help text, or None if it's a plain flag.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    // Positional arguments, as shown in help text.
+    pub args: &'static str,
+    pub min_args: usize,
+    pub max_args: usize,
+    pub summary: &'static str,
+    pub help: &'static str,
+    pub options: &'static [Opt],
+    // Options that used to be given as positional arguments, before and after the new ones.
+    // Trailing ones can be given up to the count listed.
+    pub legacy_leading: &'static [&'static str],
+    pub legacy_trailing: Option<(&'static str, usize)>,
+}
+
+// Shared by the palette command and the graphics commands that read palettes.
+const MAPPING: Opt = Opt {
+    name: "mapping",
+    value: Some("name"),
+    help: "How 3-bit color levels map to 8-bit RGB.  linear (default) scales 7 to 0xFF, shift \
+           scales 7 to 0xE0, and hardware uses the uneven levels of real hardware.  RGB colors \
+           are rounded to the nearest level.",
+};
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "fix",
+        args: "src_file.bin [dst_file.bin]",
+        min_args: 1,
+        max_args: 2,
+        summary: "Fix the checksum of a Megadrive rom",
+        help: "\
+Fixes the checksum of src_file.bin and saves it as dst_file.bin, or back to
+src_file.bin if no output is given.  The output is written even if the checksum
+was already right.  --if-newer doesn't apply to this command.",
+        options: &[
+            Opt {
+                name: "check",
+                value: None,
+                help: "Only check the checksum, without writing anything.  Exits with code 4 \
+                       if it's wrong.",
+            },
+            Opt {
+                name: "json",
+                value: Some("file"),
+                help: "Write a JSON report of the stored and calculated checksums to this file, \
+                       or \"-\" for stdout.",
+            },
+        ],
+        legacy_leading: &[],
+        legacy_trailing: None,
+    },
+    Command {
+        name: "header",
+        args: "src_file.bin",
+        min_args: 1,
+        max_args: 1,
+        summary: "Show the header of a Megadrive rom",
+        help: "\
+Prints every field of the header of src_file.bin, from 0x100 to 0x1FF.
+
+Problems are reported as warnings: a system type that doesn't start with SEGA,
+a rom start or end address that doesn't match the file, a region field with
+characters other than J, U, E or a hex digit, and a wrong checksum.",
+        options: &[],
+        legacy_leading: &[],
+        legacy_trailing: None,
+    },
+    Command {
+        name: "setheader",
+        args: "src_file.bin [dst_file.bin]",
+        min_args: 1,
+        max_args: 2,
+        summary: "Change fields in the header of a Megadrive rom",
+        help: "\
+Sets header fields of src_file.bin, fixes the checksum and saves it as
+dst_file.bin, or back to src_file.bin if no output is given.
+
+Text fields are padded with spaces: system, copyright, domestic-name,
+overseas-name, serial, version (the last 2 characters of serial), io-support,
+modem and region.  Address fields are in hex: rom-start, rom-end, ram-start and
+ram-end.  rom-end can also be auto, for the last byte of the file.",
+        options: &[Opt {
+            name: "set",
+            value: Some("field=value"),
+            help: "Set a header field.  Can be given more than once.",
+        }],
+        legacy_leading: &[],
+        legacy_trailing: None,
+    },
+    Command {
+        name: "compress",
+        args: "src_file.bin [dst_file.bin]",
+        min_args: 1,
+        max_args: 2,
+        summary: "Compress art",
+        help: "\
+Compresses src_file.bin and saves it as dst_file.bin, or back to src_file.bin if
+no output is given.  Either file can be \"-\" to use stdin or stdout.
+
+The decompressor drops data that doesn't fill a 4-byte buffer.  If the length of
+src_file.bin isn't a multiple of 4, compress will warn about this by default.",
+        options: &[
+            Opt {
+                name: "best",
+                value: None,
+                help: "Produce smaller output than the original compressor.  This won't match \
+                       the original data byte-for-byte.",
+            },
+            Opt {
+                name: "verify",
+                value: None,
+                help: "Decompress the result and fail if it doesn't match src_file.bin.",
+            },
+            Opt {
+                name: "unaligned",
+                value: Some("policy"),
+                help: "What to do with input that isn't a multiple of 4 bytes: warn (default), \
+                       refuse, pad (with zeros) or pad:XX (with the hex byte XX).",
+            },
+        ],
+        legacy_leading: &[],
+        legacy_trailing: None,
+    },
+    Command {
+        name: "decompress",
+        args: "src_file.bin [dst_file.bin]",
+        min_args: 1,
+        max_args: 2,
+        summary: "Decompress art",
+        help: "\
+Decompresses src_file.bin and saves it as dst_file.bin, or back to src_file.bin
+if no output is given.  Either file can be \"-\" to use stdin or stdout.",
+        options: &[
+            Opt {
+                name: "no-buf",
+                value: None,
+                help: "Disable an intended(?) part of the decompression that can lead to data \
+                       being discarded.",
+            },
+            Opt {
+                name: "offset",
+                value: Some("hex"),
+                help: "Decompress the block starting at this offset inside src_file.bin, such as \
+                       a whole rom, and report how many bytes the compressed block took up.",
+            },
+        ],
+        legacy_leading: &[],
+        legacy_trailing: Some(("offset", 1)),
+    },
+    Command {
+        name: "convert",
+        args: "src_file.ext dst_file.ext",
+        min_args: 2,
+        max_args: 2,
+        summary: "Convert between background mapping types",
+        help: "\
+Converts between bgmap types.  Type will be inferred using the file extension
+(bgword, bgpalm or bgbyte).  If you are using the bgpal type, specify the bgpalm
+file.  bgpalp will be created or obtained automatically.
+
+Either file can be \"-\" to use stdin or stdout, which are always bgword.",
+        options: &[
+            Opt {
+                name: "common-word",
+                value: Some("hex"),
+                help: "When converting from a smaller format to a larger one (bgbyte -> \
+                       bgpal/bgword or bgbyte/bgpal -> bgword), OR this into every word.  \
+                       Defaults to 0.",
+            },
+            Opt {
+                name: "force-word-in",
+                value: None,
+                help: "Read src_file.ext as bgword, whatever its extension.",
+            },
+            Opt {
+                name: "force-word-out",
+                value: None,
+                help: "Write dst_file.ext as bgword, whatever its extension.",
+            },
+        ],
+        legacy_leading: &["common-word"],
+        legacy_trailing: None,
+    },
+    Command {
+        name: "scan",
+        args: "src_file.bin",
+        min_args: 1,
+        max_args: 1,
+        summary: "Search a file for compressed art",
+        help: "\
+Searches src_file.bin for data that looks like compressed art, and lists the
+offset, compressed length and decompressed length of each block found.",
+        options: &[
+            Opt {
+                name: "min-tiles",
+                value: Some("count"),
+                help: "Only list blocks that decompress to at least this many tiles.  Defaults to 4.",
+            },
+            Opt {
+                name: "max-tiles",
+                value: Some("count"),
+                help: "Only list blocks that decompress to at most this many tiles.  Defaults to \
+                       2048, which fills VRAM.",
+            },
+            Opt {
+                name: "min-ratio",
+                value: Some("ratio"),
+                help: "Only list blocks at least this many times larger once decompressed.  \
+                       Defaults to 1.5.",
+            },
+        ],
+        legacy_leading: &[],
+        legacy_trailing: Some(("min-tiles", 1)),
+    },
+    Command {
+        name: "dump",
+        args: "src_file.bin",
+        min_args: 1,
+        max_args: 1,
+        summary: "List the commands in a compressed block",
+        help: "\
+Lists every command in a compressed block with its input offset, type, length,
+cache distance and the output range it produces.",
+        options: &[Opt {
+            name: "offset",
+            value: Some("hex"),
+            help: "Where the block starts in src_file.bin.  Defaults to 0.",
+        }],
+        legacy_leading: &[],
+        legacy_trailing: Some(("offset", 1)),
+    },
+    Command {
+        name: "render",
+        args: "src_file.bin dst_file.png",
+        min_args: 2,
+        max_args: 2,
+        summary: "Render 4bpp tiles to a PNG",
+        help: "\
+Renders the 4bpp tiles in src_file.bin to a PNG tile sheet.",
+        options: &[
+            Opt {
+                name: "width",
+                value: Some("tiles"),
+                help: "Width of the tile sheet in tiles.  Required.",
+            },
+            Opt {
+                name: "compressed",
+                value: None,
+                help: "Decompress src_file.bin first.",
+            },
+            Opt {
+                name: "palette",
+                value: Some("file"),
+                help: "Palette to use, in any format the palette command reads (other \
+                       extensions are raw CRAM words).  Without it, a grayscale palette is used.",
+            },
+            MAPPING,
+        ],
+        legacy_leading: &["width"],
+        legacy_trailing: Some(("palette", 1)),
+    },
+    Command {
+        name: "import",
+        args: "src_file.png dst_file.bin",
+        min_args: 2,
+        max_args: 2,
+        summary: "Turn a PNG tile sheet into 4bpp tiles",
+        help: "\
+Cuts src_file.png into 8x8 tiles and saves them as 4bpp tile data.
+
+Indexed images use their own palette indexes, which must be below 16.
+Truecolor images need a palette, and every pixel must match one of its first 16
+colors once both are rounded to the nearest color level (see --mapping).  Fully
+transparent pixels become color 0.",
+        options: &[
+            Opt {
+                name: "compressed",
+                value: None,
+                help: "Compress the tiles.",
+            },
+            Opt {
+                name: "palette",
+                value: Some("file"),
+                help: "Palette to match truecolor pixels against, in any format the palette \
+                       command reads (other extensions are raw CRAM words).",
+            },
+            MAPPING,
+        ],
+        legacy_leading: &[],
+        legacy_trailing: Some(("palette", 1)),
+    },
+    Command {
+        name: "renderbg",
+        args: "src_file.ext tiles.bin dst_file.png",
+        min_args: 3,
+        max_args: 3,
+        summary: "Render a background mapping and its tiles to a PNG",
+        help: "\
+Renders the background mapping src_file.ext (see the convert command for types)
+using the tiles in tiles.bin.",
+        options: &[
+            Opt {
+                name: "width",
+                value: Some("tiles"),
+                help: "Width of the background in tiles.  Required.",
+            },
+            Opt {
+                name: "common-word",
+                value: Some("hex"),
+                help: "OR this into every word of the mapping (see the convert command).",
+            },
+            Opt {
+                name: "tile-base",
+                value: Some("hex"),
+                help: "Tile index of the first tile in tiles.bin.  Defaults to 0.",
+            },
+            Opt {
+                name: "compressed",
+                value: None,
+                help: "Decompress tiles.bin first.",
+            },
+            Opt {
+                name: "palette",
+                value: Some("file"),
+                help: "Can be given up to four times.  Palettes are joined together, so one \
+                       file can hold all four palette lines, or each file can hold one line.",
+            },
+            MAPPING,
+        ],
+        legacy_leading: &["width", "common-word", "tile-base"],
+        legacy_trailing: Some(("palette", 4)),
+    },
+    Command {
+        name: "buildbg",
+        args: "src_file.png tiles.bin dst_file.ext",
+        min_args: 3,
+        max_args: 3,
+        summary: "Build tiles and a background mapping from a PNG",
+        help: "\
+Cuts src_file.png into 8x8 tiles, saves each unique tile to tiles.bin, and saves
+a background mapping for the image as dst_file.ext (see the convert command).
+Tiles that are flipped copies of another tile are reused with the flip bits set.
+
+Indexed images use palette indexes below 0x40, where each group of 16 is a
+palette line.  Truecolor images need palettes (see renderbg), and each tile must
+fit in one palette line.  A warning is given if the mapping type can't hold the
+palette or flip bits.",
+        options: &[
+            Opt {
+                name: "tile-base",
+                value: Some("hex"),
+                help: "Added to every tile index in the mapping.  Defaults to 0.",
+            },
+            Opt {
+                name: "no-flip",
+                value: None,
+                help: "Only reuse exact copies of tiles.",
+            },
+            Opt {
+                name: "compressed",
+                value: None,
+                help: "Compress tiles.bin.",
+            },
+            Opt {
+                name: "palette",
+                value: Some("file"),
+                help: "Can be given up to four times, as with renderbg.",
+            },
+            MAPPING,
+        ],
+        legacy_leading: &["tile-base"],
+        legacy_trailing: Some(("palette", 4)),
+    },
+    Command {
+        name: "palette",
+        args: "src_file.ext dst_file.ext",
+        min_args: 2,
+        max_args: 2,
+        summary: "Convert between palette formats",
+        help: "\
+Converts between palette formats.  Format will be inferred using the file
+extension: .bin/.cram (raw CRAM words), .gpl (GIMP), .pal (JASC), .act (Adobe)
+or .hex/.txt (CRAM words in hex, as in a disassembly).",
+        options: &[MAPPING],
+        legacy_leading: &[],
+        legacy_trailing: Some(("mapping", 1)),
+    },
+    Command {
+        name: "batch",
+        args: "manifest",
+        min_args: 1,
+        max_args: 1,
+        summary: "Run the compress, decompress, convert and fix commands in a manifest",
+        help: "\
+Runs every command listed in manifest, in parallel where they don't use each
+other's files.  --if-newer applies to every command in the manifest.
+
+A plain text manifest has one command per line, written as on the command line.
+Arguments with spaces can be quoted, and # starts a comment:
+
+  compress --best art/title.bin build/title.cmp
+  decompress build/title.cmp build/title.bin
+
+A manifest ending in .toml has a table for each command instead.  input and
+output are the command's files, and other keys are its options:
+
+  [[compress]]
+  input = \"art/title.bin\"
+  output = \"build/title.cmp\"
+  best = true",
+        options: &[Opt {
+            name: "jobs",
+            value: Some("count"),
+            help: "Most commands to run at once.  Defaults to the number of CPUs.",
+        }],
+        legacy_leading: &[],
+        legacy_trailing: None,
+    },
+];
+
+// Old command names, with the command and options they stand for.
+const ALIASES: &[(&str, &str, &[&str])] = &[
+    ("decompressnobuf", "decompress", &["no-buf"]),
+    ("convertwordin", "convert", &["force-word-in"]),
+    ("convertwordout", "convert", &["force-word-out"]),
+];
+
+pub struct Matches {
+    pub command: &'static Command,
+    pub args: Vec<String>,
+    options: Vec<(&'static str, String)>,
+}
+
+impl Matches {
+    pub fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| *option == name)
+    }
+
+    // The value of an option.  If it was given more than once, the last one wins.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).last().copied()
+    }
+
+    // Everything that decides what the command does, for telling commands apart in a cache.  The
+    // tool's version is included, since a new version can give different output.  Options are
+    // sorted by name, keeping the order of repeated ones, which can matter.
+    pub fn key(&self) -> String {
+        let mut parts = vec![
+            format!("puyomdtool-{}", env!("CARGO_PKG_VERSION")),
+            self.command.name.to_string(),
+        ];
+        let mut options: Vec<&(&str, String)> = self.options.iter().collect();
+        options.sort_by_key(|(name, _)| *name);
+        for (name, value) in options {
+            parts.push(if value.is_empty() {
+                format!("--{name}")
+            } else {
+                format!("--{name}={value}")
+            });
+        }
+        parts.push("--".into());
+        parts.extend(self.args.iter().cloned());
+        parts
+            .iter()
+            .map(|part| format!("{part:?}"))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(option, _)| *option == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+pub enum Parsed {
+    // Show help for one command, or for the whole tool.
+    Help(Option<&'static Command>),
+    Run {
+        if_newer: bool,
+        depfile: Option<String>,
+        cache: Option<String>,
+        matches: Matches,
+    },
+}
+
+fn find_command(name: &str) -> Result<&'static Command, PMDTError> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name)
+        .ok_or_else(|| PMDTError::UnknownCommand {
+            command: name.to_string(),
+        })
+}
+
+// Reads the value of a global option such as --depfile, either after an "=" or as the next
+// argument.  Returns None if arg isn't that option.
+fn global_value<'a>(
+    name: &str,
+    arg: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Result<Option<String>, PMDTError> {
+    let Some(arg_name) = arg.strip_prefix("--") else {
+        return Ok(None);
+    };
+    if let Some(value) = arg_name
+        .strip_prefix(name)
+        .and_then(|value| value.strip_prefix('='))
+    {
+        return Ok(Some(value.to_string()));
+    }
+    if arg_name != name {
+        return Ok(None);
+    }
+    rest.next()
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| PMDTError::InvalidArgument {
+            argument: arg.to_string(),
+            reason: "Expected a value after this option".into(),
+        })
+}
+
+// Parses the arguments after the program name.
+pub fn parse(args: &[String]) -> Result<Parsed, PMDTError> {
+    let mut if_newer = false;
+    let mut depfile = None;
+    let mut cache = None;
+    let mut args = args.iter().peekable();
+
+    // Options before the command.  Other than --if-newer, --depfile, --cache and --help, these are
+    // passed on to the command.
+    let mut early = Vec::new();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        match arg.as_str() {
+            "--if-newer" => if_newer = true,
+            "--help" => return Ok(Parsed::Help(None)),
+            _ => {
+                if let Some(value) = global_value("depfile", arg, &mut args)? {
+                    depfile = Some(value);
+                } else if let Some(value) = global_value("cache", arg, &mut args)? {
+                    cache = Some(value);
+                } else {
+                    early.push(arg);
+                }
+            }
+        }
+    }
+
+    let name = match args.next() {
+        Some(name) if name == "ifnewer" => {
+            eprintln!("[WARN] \"ifnewer\" is deprecated, use \"--if-newer\" instead.");
+            if_newer = true;
+            match args.next() {
+                Some(name) => name,
+                None => return Ok(Parsed::Help(None)),
+            }
+        }
+        Some(name) if name == "help" => {
+            return match args.next() {
+                Some(name) => Ok(Parsed::Help(Some(find_command(name)?))),
+                None => Ok(Parsed::Help(None)),
+            };
+        }
+        Some(name) => name,
+        None => return Ok(Parsed::Help(None)),
+    };
+
+    let mut options: Vec<(&'static str, String)> = Vec::new();
+    let command = match ALIASES.iter().find(|(alias, _, _)| alias == name) {
+        Some((alias, new_name, flags)) => {
+            let flags: Vec<String> = flags.iter().map(|flag| format!("--{flag}")).collect();
+            eprintln!(
+                "[WARN] \"{alias}\" is deprecated, use \"{new_name} {}\" instead.",
+                flags.join(" ")
+            );
+            let command = find_command(new_name)?;
+            for flag in flags.iter() {
+                options.push((find_option(command, flag)?.name, String::new()));
+            }
+            command
+        }
+        None => find_command(name)?,
+    };
+
+    let mut positional = Vec::new();
+    let mut only_positional = false;
+    let mut rest = early.into_iter().chain(args);
+    while let Some(arg) = rest.next() {
+        if only_positional || !arg.starts_with("--") {
+            positional.push(arg.clone());
+            continue;
+        }
+        if arg == "--" {
+            only_positional = true;
+            continue;
+        }
+        if arg == "--help" {
+            return Ok(Parsed::Help(Some(command)));
+        }
+        if arg == "--if-newer" {
+            if_newer = true;
+            continue;
+        }
+        if let Some(value) = global_value("depfile", arg, &mut rest)? {
+            depfile = Some(value);
+            continue;
+        }
+        if let Some(value) = global_value("cache", arg, &mut rest)? {
+            cache = Some(value);
+            continue;
+        }
+
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        let option = find_option(command, flag)?;
+        let value = match (option.value, inline_value) {
+            (None, None) => String::new(),
+            (None, Some(_)) => {
+                return Err(PMDTError::InvalidArgument {
+                    argument: arg.clone(),
+                    reason: "This option doesn't take a value".into(),
+                });
+            }
+            (Some(_), Some(value)) => value,
+            (Some(_), None) => rest
+                .next()
+                .cloned()
+                .ok_or_else(|| PMDTError::InvalidArgument {
+                    argument: arg.clone(),
+                    reason: "Expected a value after this option".into(),
+                })?,
+        };
+        options.push((option.name, value));
+    }
+
+    // Move old positional arguments into the options they became.
+    if positional.len() > command.max_args
+        && (!command.legacy_leading.is_empty() || command.legacy_trailing.is_some())
+    {
+        let leading = command.legacy_leading.len();
+        let trailing_max = command.legacy_trailing.map_or(0, |(_, count)| count);
+        if positional.len() >= leading + command.min_args
+            && positional.len() <= leading + command.max_args + trailing_max
+        {
+            let mut moved = Vec::new();
+            for (name, value) in command
+                .legacy_leading
+                .iter()
+                .zip(positional.drain(..leading))
+            {
+                moved.push(*name);
+                options.push((name, value));
+            }
+            if let Some((name, _)) = command.legacy_trailing
+                && positional.len() > command.max_args
+            {
+                moved.push(name);
+                for value in positional.split_off(command.max_args) {
+                    options.push((name, value));
+                }
+            }
+            let moved: Vec<String> = moved.iter().map(|name| format!("--{name}")).collect();
+            if let [option] = moved.as_slice() {
+                eprintln!(
+                    "[WARN] Giving {option} as a positional argument is deprecated, use the \
+                     option instead."
+                );
+            } else {
+                eprintln!(
+                    "[WARN] Giving {} as positional arguments is deprecated, use the options \
+                     instead.",
+                    moved.join(", ")
+                );
+            }
+        }
+    }
+
+    if positional.len() < command.min_args || positional.len() > command.max_args {
+        return Err(PMDTError::InvalidNumOfArguments {
+            command: command.name.to_string(),
+        });
+    }
+
+    Ok(Parsed::Run {
+        if_newer,
+        depfile,
+        cache,
+        matches: Matches {
+            command,
+            args: positional,
+            options,
+        },
+    })
+}
+
+fn find_option(command: &'static Command, flag: &str) -> Result<&'static Opt, PMDTError> {
+    command
+        .options
+        .iter()
+        .find(|option| flag.strip_prefix("--") == Some(option.name))
+        .ok_or_else(|| PMDTError::InvalidArgument {
+            argument: flag.to_string(),
+            reason: format!("Unknown option for {}", command.name),
+        })
+}
+
+// Wraps text to fit in width columns, indenting every line after the first.  Sentences are kept
+// two spaces apart.
+fn wrap(text: &str, width: usize, indent: usize) -> String {
+    let mut output = String::new();
+    let mut line_len = 0;
+    let mut space = "";
+    for word in text.split_whitespace() {
+        if line_len != 0 && line_len + space.len() + word.len() > width {
+            output.push('\n');
+            output.push_str(&" ".repeat(indent));
+            line_len = 0;
+        } else {
+            output.push_str(space);
+            line_len += space.len();
+        }
+        output.push_str(word);
+        line_len += word.len();
+        space = if word.ends_with('.') { "  " } else { " " };
+    }
+    output
+}
+
+pub fn print_help() {
+    println!("=== puyomdtool by Nasina");
+    println!();
+    println!(
+        "Usage: puyomdtool [--if-newer] [--depfile file] [--cache file] command [options] args..."
+    );
+    println!("       puyomdtool help command");
+    println!();
+    println!("Commands:");
+    for command in COMMANDS {
+        println!("  {:<12}{}", command.name, command.summary);
+    }
+    println!();
+    println!("Global options:");
+    println!("  --if-newer  Skip the command if the output file is newer than the input file (if");
+    println!("              it even exists).  This is only meant to be used in build systems to");
+    println!("              speed up build times.");
+    println!("  --depfile <file>");
+    println!("              Write a Makefile rule listing every file the command read and");
+    println!("              wrote, for make or ninja to track dependencies with.  It's left as");
+    println!("              it is when --if-newer skips the command.");
+    println!("  --cache <file>");
+    println!("              Skip the command if the files it read and wrote, and its options,");
+    println!("              are the same as when it last ran.  Hashes of the files are kept in");
+    println!("              this file, which can be shared by many commands.");
+    println!("  --help      Show help for the tool, or for a command when given after it.");
+    println!();
+    println!("Options can be given as \"--name value\" or \"--name=value\".  Hex values can start");
+    println!("with \"0x\" or \"$\".");
+    println!();
+    println!("Messages, warnings and errors are printed to stderr.  On failure the exit code is");
+    println!("2 for a bad command line, 3 if a file couldn't be read or written, 4 if an input");
+    println!("file isn't valid (or fix --check found a wrong checksum), and 5 if data couldn't");
+    println!("be compressed safely.");
+    println!();
+    println!(
+        "Older spellings such as \"ifnewer\", \"decompressnobuf\" or \"convert 8000 a b\" still"
+    );
+    println!("work, but print a warning.");
+}
+
+pub fn print_command_help(command: &Command) {
+    println!(
+        "Usage: puyomdtool {} [options] {}",
+        command.name, command.args
+    );
+    println!();
+    println!("{}", command.help);
+    println!();
+    println!("Options:");
+    let mut options: Vec<(String, &str)> = command
+        .options
+        .iter()
+        .map(|option| match option.value {
+            Some(value) => (format!("--{} <{value}>", option.name), option.help),
+            None => (format!("--{}", option.name), option.help),
+        })
+        .collect();
+    if !matches!(command.name, "fix" | "header") {
+        options.push((
+            "--if-newer".into(),
+            "Skip if the output is newer than the input.",
+        ));
+    }
+    options.push((
+        "--depfile <file>".into(),
+        "Write a Makefile rule listing the files read and written.",
+    ));
+    options.push((
+        "--cache <file>".into(),
+        "Skip if nothing changed since the run recorded in this file.",
+    ));
+    options.push(("--help".into(), "Show this help."));
+
+    let column = options
+        .iter()
+        .map(|(flag, _)| flag.len())
+        .max()
+        .unwrap_or(0)
+        + 4;
+    for (flag, help) in options {
+        println!(
+            "  {flag:<width$}{}",
+            wrap(help, 80 - column, column),
+            width = column - 2
+        );
+    }
+}
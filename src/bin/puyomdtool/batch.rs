@@ -0,0 +1,388 @@
+// Runs many commands from a manifest file in one process.
+//
+// A plain text manifest has one command per line, written the same way as on the command line
+// (without "puyomdtool").  A TOML manifest (one ending in .toml) has a table for each command:
+//
+//   [[compress]]
+//   input = "art/title.bin"
+//   output = "build/title.cmp"
+//   best = true
+//
+// Keys other than input and output are the command's options, with true turning on a flag.
+//
+// Commands that don't touch each other's files run in parallel.  A command waits for every
+// earlier command that writes a file it reads or writes, or reads a file it writes.
+
+use crate::args::{self, Matches, Parsed};
+use puyomdtool::{
+    PMDTError,
+    cache::Cache,
+    depfile::{self, Touched},
+};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Commands that can be listed in a manifest.
+const BATCH_COMMANDS: &[&str] = &["compress", "decompress", "convert", "fix"];
+
+// A problem with a manifest, and the line it's on.
+type ManifestError = (usize, String);
+
+struct Entry {
+    line: usize,
+    check_newer: bool,
+    depfile: Option<String>,
+    matches: Matches,
+    inputs: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    // Entries in the same wave don't depend on each other.  Each wave runs after the one before.
+    wave: usize,
+}
+
+// Splits a line of a text manifest into arguments.  Arguments can be quoted to hold spaces, and
+// anything after an unquoted "#" is a comment.
+fn split_line(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("Missing closing quote".into()),
+                    }
+                }
+            }
+            c if c.is_whitespace() => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+fn parse_text(text: &str) -> Result<Vec<(usize, Vec<String>)>, ManifestError> {
+    let mut commands = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let args = split_line(line).map_err(|reason| (i + 1, reason))?;
+        if !args.is_empty() {
+            commands.push((i + 1, args));
+        }
+    }
+    Ok(commands)
+}
+
+// Reads a TOML value: a string, an integer or a boolean.  Integers are written out in hex for
+// options that take hex values.
+fn toml_value(text: &str, hex: bool) -> Result<Option<String>, String> {
+    let text = text.trim();
+    if let Some(string) = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    {
+        let mut value = String::new();
+        let mut chars = string.chars();
+        while let Some(c) = chars.next() {
+            value.push(match (c, c == '\\') {
+                (_, true) => match chars.next() {
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    _ => return Err("Unsupported escape in string".into()),
+                },
+                (c, false) => c,
+            });
+        }
+        return Ok(Some(value));
+    }
+    if let Some(string) = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return Ok(Some(string.to_string()));
+    }
+    match text {
+        "true" => return Ok(Some(String::new())),
+        "false" => return Ok(None),
+        _ => (),
+    }
+
+    let digits = text.replace('_', "");
+    let number = match digits.strip_prefix("0x") {
+        Some(digits) => u64::from_str_radix(digits, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("Unsupported value \"{text}\""))?;
+    Ok(Some(if hex {
+        format!("0x{number:X}")
+    } else {
+        number.to_string()
+    }))
+}
+
+// Reads the subset of TOML needed for a manifest: [[command]] tables holding key = value pairs.
+fn parse_toml(text: &str) -> Result<Vec<(usize, Vec<String>)>, ManifestError> {
+    // The command name and options, and its input and output, for each table.
+    let mut tables: Vec<(usize, Vec<String>, Vec<String>)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |reason: String| (i + 1, reason);
+        let line = match line.trim() {
+            line if line.starts_with('#') => "",
+            line => line,
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix("[[")
+            .and_then(|line| line.strip_suffix("]]"))
+        {
+            tables.push((i + 1, vec![name.trim().to_string()], Vec::new()));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("Expected [[command]] or key = value".into()))?;
+        let key = key.trim().trim_matches('"');
+        let (_, options, files) = tables
+            .last_mut()
+            .ok_or_else(|| error("Expected a [[command]] table first".into()))?;
+
+        // Drop trailing comments from values that aren't strings.
+        let value = match value.trim() {
+            value if value.starts_with(['"', '\'']) => value,
+            value => value.split('#').next().unwrap_or(value),
+        };
+        let hex = args::COMMANDS
+            .iter()
+            .find(|command| command.name == options[0])
+            .and_then(|command| command.options.iter().find(|option| option.name == key))
+            .is_some_and(|option| option.value == Some("hex"));
+        let value = toml_value(value, hex).map_err(error)?;
+
+        match (key, value) {
+            ("input", Some(value)) => files.insert(0, value),
+            ("output", Some(value)) => files.push(value),
+            (_, None) => (),
+            (_, Some(value)) if value.is_empty() => options.push(format!("--{key}")),
+            (_, Some(value)) => {
+                options.push(format!("--{key}"));
+                options.push(value);
+            }
+        }
+    }
+
+    Ok(tables
+        .into_iter()
+        .map(|(line, mut args, files)| {
+            args.push("--".into());
+            args.extend(files);
+            (line, args)
+        })
+        .collect())
+}
+
+// Makes paths comparable by dropping "." components.
+fn normalize(filename: &str) -> PathBuf {
+    Path::new(filename)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// Options that name a file the command writes.  Other options that take a file are read.
+const OUTPUT_OPTIONS: &[&str] = &["json"];
+
+// Lists the files a command reads and writes, including bgpalp files next to bgpalm mappings,
+// files given to options and its depfile.
+fn files(matches: &Matches, depfile: Option<&str>) -> (Vec<String>, Vec<String>) {
+    let src = &matches.args[0];
+    let dst = matches.args.get(1).unwrap_or(src);
+    let with_palp = |filename: &str| {
+        let mut files = vec![filename.to_string()];
+        if matches.command.name == "convert" && filename.ends_with("bgpalm") {
+            files.push(format!("{}p", &filename[..filename.len() - 1]));
+        }
+        files
+    };
+    let (mut inputs, mut outputs) = (with_palp(src), with_palp(dst));
+
+    for option in matches.command.options {
+        if option.value != Some("file") {
+            continue;
+        }
+        let values = matches.values(option.name).into_iter().map(String::from);
+        if OUTPUT_OPTIONS.contains(&option.name) {
+            outputs.extend(values);
+        } else {
+            inputs.extend(values);
+        }
+    }
+    outputs.extend(depfile.map(String::from));
+    (inputs, outputs)
+}
+
+fn read_manifest(manifest_filename: &str, check_newer: bool) -> Result<Vec<Entry>, PMDTError> {
+    let text = std::fs::read_to_string(manifest_filename).map_err(|source| PMDTError::Io {
+        path: manifest_filename.into(),
+        source,
+    })?;
+    let invalid = |line: usize, reason: String| PMDTError::InvalidManifest {
+        path: manifest_filename.into(),
+        line,
+        reason,
+    };
+
+    let commands = if manifest_filename.ends_with(".toml") {
+        parse_toml(&text)
+    } else {
+        parse_text(&text)
+    }
+    .map_err(|(line, reason)| invalid(line, reason))?;
+
+    let mut entries: Vec<Entry> = Vec::new();
+    for (line, args) in commands {
+        let (if_newer, depfile, matches) = match args::parse(&args) {
+            Ok(Parsed::Run { cache: Some(_), .. }) => {
+                return Err(invalid(
+                    line,
+                    "Give --cache to the batch command instead".into(),
+                ));
+            }
+            Ok(Parsed::Run {
+                if_newer,
+                depfile,
+                matches,
+                ..
+            }) => (if_newer, depfile, matches),
+            Ok(Parsed::Help(_)) => return Err(invalid(line, "Help can't be shown here".into())),
+            Err(source) => {
+                return Err(PMDTError::BatchEntry {
+                    path: manifest_filename.into(),
+                    line,
+                    source: Box::new(source),
+                });
+            }
+        };
+        if !BATCH_COMMANDS.contains(&matches.command.name) {
+            return Err(invalid(
+                line,
+                format!("Only {} can be run in a batch", BATCH_COMMANDS.join(", ")),
+            ));
+        }
+        // Commands run in parallel, so they can't share stdin and stdout.
+        let (inputs, outputs) = files(&matches, depfile.as_deref());
+        if inputs.iter().chain(outputs.iter()).any(|file| file == "-") {
+            return Err(invalid(
+                line,
+                "stdin and stdout can't be used in a batch".into(),
+            ));
+        }
+        let inputs: Vec<PathBuf> = inputs.iter().map(|file| normalize(file)).collect();
+        let outputs: Vec<PathBuf> = outputs.iter().map(|file| normalize(file)).collect();
+        let wave = entries
+            .iter()
+            .filter(|earlier| {
+                inputs.iter().any(|file| earlier.outputs.contains(file))
+                    || outputs
+                        .iter()
+                        .any(|file| earlier.inputs.contains(file) || earlier.outputs.contains(file))
+            })
+            .map(|earlier| earlier.wave + 1)
+            .max()
+            .unwrap_or(0);
+        entries.push(Entry {
+            line,
+            check_newer: check_newer || if_newer,
+            depfile,
+            matches,
+            inputs,
+            outputs,
+            wave,
+        });
+    }
+    Ok(entries)
+}
+
+pub fn run(
+    manifest_filename: &str,
+    check_newer: bool,
+    jobs: Option<usize>,
+    cache: Option<&Cache>,
+) -> Result<(), PMDTError> {
+    let entries = read_manifest(manifest_filename, check_newer)?;
+    // The commands run on other threads, so the files they touch are gathered here and passed on
+    // to the depfile for the whole batch.
+    let touched = Mutex::new(Touched {
+        inputs: vec![manifest_filename.into()],
+        outputs: Vec::new(),
+    });
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .max(1);
+
+    let waves = entries
+        .iter()
+        .map(|entry| entry.wave + 1)
+        .max()
+        .unwrap_or(0);
+    for wave in 0..waves {
+        let todo: Vec<&Entry> = entries.iter().filter(|entry| entry.wave == wave).collect();
+        let next = AtomicUsize::new(0);
+        let errors = Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.min(todo.len()) {
+                scope.spawn(|| {
+                    while let Some(entry) = todo.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let (result, files) = depfile::track(|| {
+                            crate::execute(
+                                entry.check_newer,
+                                entry.depfile.as_deref(),
+                                cache,
+                                &entry.matches,
+                            )
+                        });
+                        touched.lock().unwrap().merge(files);
+                        if let Err(source) = result {
+                            errors.lock().unwrap().push(PMDTError::BatchEntry {
+                                path: manifest_filename.into(),
+                                line: entry.line,
+                                source: Box::new(source),
+                            });
+                        }
+                    }
+                });
+            }
+        });
+
+        // Let every command in this wave finish, but don't start any that could depend on one
+        // that failed.
+        let mut errors = errors.into_inner().unwrap();
+        errors.sort_by_key(|error| match error {
+            PMDTError::BatchEntry { line, .. } => *line,
+            _ => 0,
+        });
+        let mut errors = errors.into_iter();
+        if let Some(first) = errors.next() {
+            for error in errors {
+                eprintln!("Hit an Error: {error}");
+            }
+            let skipped = entries.iter().filter(|entry| entry.wave > wave).count();
+            if skipped != 0 {
+                eprintln!("[MSG] Skipped {skipped} command(s) after the failure.");
+            }
+            return Err(first);
+        }
+    }
+
+    depfile::record(touched.into_inner().unwrap());
+    Ok(())
+}
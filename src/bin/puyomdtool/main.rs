@@ -1,107 +1,275 @@
+mod args;
+mod batch;
+
+use args::{Matches, Parsed};
 use puyomdtool::{
-    PMDTError, compress::Compress, convert::Convert, decompress::Decompress,
+    PMDTError,
+    build_background::BuildBackground,
+    cache::Cache,
+    compress::{Compress, CompressMode, CompressOptions, UnalignedPolicy},
+    convert::Convert,
+    decompress::Decompress,
+    depfile,
+    dump::Dump,
     fix_checksum::FixChecksum,
+    header::{EditHeader, ShowHeader},
+    import::Import,
+    palette::{ColorMapping, ConvertPalette},
+    render::Render,
+    render_background::RenderBackground,
+    scan::{Scan, ScanOptions},
 };
-use std::{env, error::Error};
-
-fn print_help() {
-    println!("=== puyomdtool by Nasina");
-    println!();
-    println!("Each command can be prefixed with \"ifnewer\" to have the tool skip operation if");
-    println!("the output file is newer than the input file (if it even exists).  This does not");
-    println!("apply to the fix command.  This is only meant to be used in build systems to speed");
-    println!("up build times.");
-    println!();
-    println!("Usage 1: puyomdtool fix src_file.bin dst_file.bin");
-    println!("  - This will fix the checksum of any Megadrive rom passed to it.");
-    println!();
-    println!("Usage 2: puyomdtool [compress|decompress(nobuf)] src_file.bin dst_file.bin");
-    println!("  - This will compress / decompress src_file.bin and save it as dst_file.bin");
-    println!("  - decompressnobuf disables an intended(?) part of the decompression that can lead");
-    println!("    to data being discarded.");
-    println!();
-    println!("Usage 3: puyomdtool convert(wordin|wordout) common_word src_file.ext dst_file.ext");
-    println!("  - Converts between bgmap types.  Type will be inferred using the file extension.");
-    println!("  - If you are using the bgpal type, specify the bgpalm file.");
-    println!("    bgpalp will be created or obtained automatically.");
-    println!("  - When converting from a smaller data format to a larger format ");
-    println!("    (bgbyte -> bgpal/bgword or bgbyte/bgpal -> bgword), common_word is used as an ");
-    println!("    OR value (Ex: byte | common_word -> word).  When converting to a smaller format");
-    println!("    this value should be set to zero.");
-    println!("  - Using \"convertwordin\" will force src_file.ext to be viewed as bgword format.");
-    println!("  - Using \"convertwordout\" will force dst_file.ext to be viewed as bgword format.");
-    println!();
+use std::{env, process::ExitCode};
+
+// Parses a hex number, such as an offset or a common_word.  It can start with "0x" or "$".
+fn parse_hex<T: TryFrom<u64>>(arg: &str) -> Result<T, PMDTError> {
+    let digits = arg
+        .strip_prefix("0x")
+        .or_else(|| arg.strip_prefix('$'))
+        .unwrap_or(arg);
+    u64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|val| T::try_from(val).ok())
+        .ok_or_else(|| PMDTError::InvalidArgument {
+            argument: arg.to_string(),
+            reason: "Expected a hex number in range".into(),
+        })
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut args: Vec<String> = env::args().collect();
-
-    // This flag will make it so that the operation will only happen if
-    // the input file is newer than the output file (or if the output
-    // file doesn't exist.)  This is mainly meant for improving disassembly
-    // build times.
-    let check_newer = if args.len() >= 2 && args[1].as_str() == "ifnewer" {
-        args.remove(1);
-        true
-    } else {
-        false
-    };
+// Parses a decimal number, such as a width in tiles.
+fn parse_dec<T: std::str::FromStr>(arg: &str) -> Result<T, PMDTError> {
+    arg.parse().map_err(|_| PMDTError::InvalidArgument {
+        argument: arg.to_string(),
+        reason: "Expected a decimal number in range".into(),
+    })
+}
+
+// Parses an optional hex option, using default if it wasn't given.
+fn hex_option<T: TryFrom<u64>>(matches: &Matches, name: &str, default: T) -> Result<T, PMDTError> {
+    matches.value(name).map_or(Ok(default), parse_hex)
+}
+
+// Gets an option that the command can't run without.
+fn required<'a>(matches: &'a Matches, name: &str) -> Result<&'a str, PMDTError> {
+    matches
+        .value(name)
+        .ok_or_else(|| PMDTError::InvalidArgument {
+            argument: format!("--{name}"),
+            reason: format!("{} needs this option", matches.command.name),
+        })
+}
+
+// What to do when compressing data that isn't a multiple of 4 bytes long.
+fn unaligned_policy(matches: &Matches) -> Result<UnalignedPolicy, PMDTError> {
+    match matches.value("unaligned") {
+        None | Some("warn") => Ok(UnalignedPolicy::Warn),
+        Some("refuse") => Ok(UnalignedPolicy::Refuse),
+        Some("pad") => Ok(UnalignedPolicy::Pad(0)),
+        Some(val) if val.starts_with("pad:") => Ok(UnalignedPolicy::Pad(parse_hex(&val[4..])?)),
+        Some(val) => Err(PMDTError::InvalidArgument {
+            argument: val.to_string(),
+            reason: "Expected refuse, warn, pad or pad:XX".into(),
+        }),
+    }
+}
+
+fn color_mapping(matches: &Matches) -> Result<ColorMapping, PMDTError> {
+    match matches.value("mapping") {
+        Some(name) => ColorMapping::from_name(name).ok_or_else(|| PMDTError::InvalidArgument {
+            argument: name.to_string(),
+            reason: "Expected linear, shift or hardware".into(),
+        }),
+        None => Ok(ColorMapping::Linear),
+    }
+}
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Hit an Error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let result: Result<(), Box<dyn Error>> = if args.len() < 2 {
-        print_help();
-        Ok(())
-    } else {
-        match args[1].as_str() {
-            "compress" if args.len() == 3 => Compress::run(&args[2], &args[2], false),
-            "compress" if args.len() == 4 => Compress::run(&args[2], &args[3], check_newer),
-            "compress" => Err(Box::new(PMDTError::InvalidNumOfArguments)),
-            "convert" if args.len() == 5 => Convert::run(
-                &args[3],
-                &args[4],
-                check_newer,
-                false,
-                false,
-                u16::from_str_radix(&args[2], 16)?,
-            ),
-            "convertwordin" if args.len() == 5 => Convert::run(
-                &args[3],
-                &args[4],
-                check_newer,
-                true,
-                false,
-                u16::from_str_radix(&args[2], 16)?,
-            ),
-            "convertwordout" if args.len() == 5 => Convert::run(
-                &args[3],
-                &args[4],
-                check_newer,
-                false,
-                true,
-                u16::from_str_radix(&args[2], 16)?,
-            ),
-            "convert" => Err(Box::new(PMDTError::InvalidNumOfArguments)),
-            "decompress" if args.len() == 3 => Decompress::run(&args[2], &args[2], false, false),
-            "decompress" if args.len() == 4 => {
-                Decompress::run(&args[2], &args[3], check_newer, false)
-            }
-            "decompress" => Err(Box::new(PMDTError::InvalidNumOfArguments)),
-            "decompressnobuf" if args.len() == 3 => {
-                Decompress::run(&args[2], &args[2], false, true)
-            }
-            "decompressnobuf" if args.len() == 4 => {
-                Decompress::run(&args[2], &args[3], check_newer, true)
-            }
-            "decompressnobuf" => Err(Box::new(PMDTError::InvalidNumOfArguments)),
-            "fix" if args.len() == 3 => FixChecksum::run(&args[2], &args[2]),
-            "fix" if args.len() == 4 => FixChecksum::run(&args[2], &args[3]),
-            "fix" => Err(Box::new(PMDTError::InvalidNumOfArguments)),
-            _ => Ok(()),
+fn run(args: Vec<String>) -> Result<(), PMDTError> {
+    let (check_newer, depfile, cache, matches) = match args::parse(&args)? {
+        Parsed::Help(None) => {
+            args::print_help();
+            return Ok(());
         }
+        Parsed::Help(Some(command)) => {
+            args::print_command_help(command);
+            return Ok(());
+        }
+        Parsed::Run {
+            if_newer,
+            depfile,
+            cache,
+            matches,
+        } => (if_newer, depfile, cache, matches),
     };
 
-    if let Err(ref e) = result {
-        println!("Hit an Error: {e}\n");
+    let cache = cache.as_deref().map(Cache::load).transpose()?;
+    let result = execute(check_newer, depfile.as_deref(), cache.as_ref(), &matches);
+    // Keep what was recorded before a failure, such as the commands in a batch that did finish.
+    match cache {
+        Some(cache) => result.and(cache.save()),
+        None => result,
+    }
+}
+
+// Runs a parsed command, and writes its depfile if one was asked for.  Batch manifests call this
+// for each command they list.
+fn execute(
+    check_newer: bool,
+    depfile: Option<&str>,
+    cache: Option<&Cache>,
+    matches: &Matches,
+) -> Result<(), PMDTError> {
+    // stdin can't be hashed before it's read, and a batch records each of its commands instead.
+    let own_cache = cache
+        .filter(|_| matches.command.name != "batch" && !matches.args.iter().any(|arg| arg == "-"));
+    let key = matches.key();
+    if own_cache.is_some_and(|cache| cache.is_fresh(&key)) {
+        return Ok(());
+    }
+
+    let (result, touched) = depfile::track(|| dispatch(check_newer, cache, matches));
+    result?;
+    if let Some(cache) = own_cache
+        && !touched.outputs.is_empty()
+    {
+        cache.update(&key, &touched)?;
     }
 
-    result
+    // Nothing is written when --if-newer or --cache skips the command, so the last depfile still
+    // holds.
+    match depfile {
+        Some(depfile) if !touched.outputs.is_empty() => touched.write(depfile),
+        _ => Ok(()),
+    }
+}
+
+fn dispatch(check_newer: bool, cache: Option<&Cache>, matches: &Matches) -> Result<(), PMDTError> {
+    let args = &matches.args;
+
+    // Commands that work in place when given one file.  Checking the modification time makes no
+    // sense then.
+    let src = args[0].as_str();
+    let (dst, in_place_check_newer) = match args.get(1) {
+        Some(dst) => (dst.as_str(), check_newer),
+        None => (src, false),
+    };
+
+    match matches.command.name {
+        "fix" if matches.flag("check") => match args.len() {
+            1 => FixChecksum::check(src, matches.value("json")),
+            _ => Err(PMDTError::InvalidArgument {
+                argument: "--check".into(),
+                reason: "Nothing is written when checking, so dst_file.bin can't be given".into(),
+            }),
+        },
+        "fix" => FixChecksum::run(src, dst, matches.value("json")),
+        "header" => ShowHeader::run(src),
+        "setheader" => EditHeader::run(src, dst, in_place_check_newer, &matches.values("set")),
+        "compress" => Compress::run(
+            src,
+            dst,
+            in_place_check_newer,
+            &CompressOptions {
+                mode: if matches.flag("best") {
+                    CompressMode::Best
+                } else {
+                    CompressMode::Greedy
+                },
+                verify: matches.flag("verify"),
+                unaligned: unaligned_policy(matches)?,
+            },
+        ),
+        "decompress" => Decompress::run(
+            src,
+            dst,
+            in_place_check_newer,
+            matches.flag("no-buf"),
+            matches.value("offset").map(parse_hex).transpose()?,
+        ),
+        "convert" => Convert::run(
+            src,
+            &args[1],
+            check_newer,
+            matches.flag("force-word-in"),
+            matches.flag("force-word-out"),
+            hex_option(matches, "common-word", 0)?,
+        ),
+        "scan" => {
+            let defaults = ScanOptions::default();
+            Scan::run(
+                src,
+                &ScanOptions {
+                    min_tiles: matches
+                        .value("min-tiles")
+                        .map_or(Ok(defaults.min_tiles), parse_dec)?,
+                    max_tiles: matches
+                        .value("max-tiles")
+                        .map_or(Ok(defaults.max_tiles), parse_dec)?,
+                    min_ratio: matches
+                        .value("min-ratio")
+                        .map_or(Ok(defaults.min_ratio), parse_dec)?,
+                },
+            )
+        }
+        "dump" => Dump::run(src, hex_option(matches, "offset", 0)?),
+        "render" => Render::run(
+            src,
+            &args[1],
+            check_newer,
+            matches.flag("compressed"),
+            parse_dec(required(matches, "width")?)?,
+            matches.value("palette"),
+            color_mapping(matches)?,
+        ),
+        "import" => Import::run(
+            src,
+            &args[1],
+            check_newer,
+            matches.flag("compressed"),
+            matches.value("palette"),
+            color_mapping(matches)?,
+        ),
+        "renderbg" => RenderBackground::run(
+            src,
+            &args[1],
+            &args[2],
+            check_newer,
+            matches.flag("compressed"),
+            parse_dec(required(matches, "width")?)?,
+            hex_option(matches, "common-word", 0)?,
+            hex_option(matches, "tile-base", 0)?,
+            &matches.values("palette"),
+            color_mapping(matches)?,
+        ),
+        "buildbg" => BuildBackground::run(
+            src,
+            &args[1],
+            &args[2],
+            check_newer,
+            matches.flag("compressed"),
+            !matches.flag("no-flip"),
+            hex_option(matches, "tile-base", 0)?,
+            &matches.values("palette"),
+            color_mapping(matches)?,
+        ),
+        "palette" => ConvertPalette::run(src, &args[1], check_newer, color_mapping(matches)?),
+        "batch" => batch::run(
+            src,
+            check_newer,
+            matches.value("jobs").map(parse_dec).transpose()?,
+            cache,
+        ),
+        name => Err(PMDTError::UnknownCommand {
+            command: name.to_string(),
+        }),
+    }
 }
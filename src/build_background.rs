@@ -0,0 +1,219 @@
+use super::PMDTError;
+use super::convert::Mapping;
+use super::import::ImportError;
+use super::palette::{ColorMapping, load_background_colors};
+use super::png::{Image, ImageData};
+use super::tiles::{NAMETABLE_HFLIP, NAMETABLE_VFLIP, TILE_SIZE};
+use std::collections::HashMap;
+
+// A set of unique tiles, along with a nametable word for each tile in the image.
+pub struct Tilemap {
+    pub tiles: Vec<u8>,
+    pub words: Vec<u16>,
+}
+
+// Splits the 8x8 tile at (tile_x, tile_y) into a palette line and 16 color indexes.
+//
+// Indexed images use palette indexes below 0x40, where the upper bits pick the palette line.
+// Other images have each tile matched against every palette line in colors until one fits all
+// of its pixels, after rounding pixels to the nearest levels in mapping (colors should already be
+// rounded).  Fully transparent pixels become color 0 and fit in any line, but opaque pixels must
+// match a color in the line, even ones matching its color 0.
+fn split_tile(
+    image: &Image,
+    colors: Option<&[[u8; 3]]>,
+    mapping: ColorMapping,
+    tile_x: usize,
+    tile_y: usize,
+) -> Result<(u8, [u8; 0x40]), ImportError> {
+    let pixel_ind = |i: usize| (tile_y * 8 + i / 8) * image.width + tile_x * 8 + i % 8;
+    let mut pixels = [0; 0x40];
+
+    match &image.data {
+        ImageData::Indexed { indexes, .. } => {
+            let mut line = None;
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let index = indexes[pixel_ind(i)];
+                if index >= 0x40 {
+                    return Err(ImportError::IndexTooLarge {
+                        x: tile_x * 8 + i % 8,
+                        y: tile_y * 8 + i / 8,
+                        index,
+                        limit: 0x40,
+                    });
+                }
+                if index & 0xF != 0 && *line.get_or_insert(index >> 4) != index >> 4 {
+                    return Err(ImportError::MixedPaletteLines { tile_x, tile_y });
+                }
+                *pixel = index & 0xF;
+            }
+            Ok((line.unwrap_or(0), pixels))
+        }
+        ImageData::Rgba(rgba) => {
+            let colors = colors.ok_or(ImportError::NeedsPalette)?;
+            'line: for (line, line_colors) in colors.chunks(0x10).take(4).enumerate() {
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let [r, g, b, a] = rgba[pixel_ind(i)];
+                    *pixel = if a == 0 {
+                        0
+                    } else {
+                        let color = mapping.snap([r, g, b]);
+                        match line_colors
+                            .iter()
+                            .position(|line_color| *line_color == color)
+                        {
+                            Some(index) => index as u8,
+                            None => continue 'line,
+                        }
+                    };
+                }
+                return Ok((line as u8, pixels));
+            }
+            Err(ImportError::NoPaletteLine { tile_x, tile_y })
+        }
+    }
+}
+
+// Packs 8x8 color indexes into 4bpp tile data, optionally flipping them.
+fn pack_tile(pixels: &[u8; 0x40], hflip: bool, vflip: bool) -> [u8; TILE_SIZE] {
+    let mut tile = [0; TILE_SIZE];
+    for y in 0..8 {
+        for x in 0..8 {
+            let src_x = if hflip { 7 - x } else { x };
+            let src_y = if vflip { 7 - y } else { y };
+            tile[y * 4 + x / 2] |= pixels[src_y * 8 + src_x] << if x & 1 == 0 { 4 } else { 0 };
+        }
+    }
+    tile
+}
+
+// Cuts an image into tiles and builds a nametable for it, reusing tiles that have already been
+// seen.  If allow_flips is set, flipped copies of a tile are reused too.  tile_base is added to
+// every tile index.  See split_tile for how pixels are matched to colors, which are rounded to the
+// nearest levels in mapping.
+pub fn build_tilemap(
+    image: &Image,
+    colors: Option<&[[u8; 3]]>,
+    mapping: ColorMapping,
+    tile_base: usize,
+    allow_flips: bool,
+) -> Result<Tilemap, ImportError> {
+    if !image.width.is_multiple_of(8) || !image.height.is_multiple_of(8) {
+        return Err(ImportError::BadSize {
+            width: image.width,
+            height: image.height,
+        });
+    }
+
+    let mut tilemap = Tilemap {
+        tiles: Vec::new(),
+        words: Vec::new(),
+    };
+    let mut seen: HashMap<[u8; TILE_SIZE], usize> = HashMap::new();
+    let colors: Option<Vec<[u8; 3]>> =
+        colors.map(|colors| colors.iter().map(|color| mapping.snap(*color)).collect());
+
+    for tile_y in 0..image.height / 8 {
+        for tile_x in 0..image.width / 8 {
+            let (line, pixels) = split_tile(image, colors.as_deref(), mapping, tile_x, tile_y)?;
+
+            // If a flipped version of this tile has already been stored, then flipping the stored
+            // tile the same way gives this one back.
+            let flips: &[(bool, bool)] = if allow_flips {
+                &[(false, false), (true, false), (false, true), (true, true)]
+            } else {
+                &[(false, false)]
+            };
+            let found = flips.iter().find_map(|(hflip, vflip)| {
+                seen.get(&pack_tile(&pixels, *hflip, *vflip))
+                    .map(|index| (*index, *hflip, *vflip))
+            });
+
+            let (index, hflip, vflip) = found.unwrap_or_else(|| {
+                let tile = pack_tile(&pixels, false, false);
+                let index = seen.len();
+                seen.insert(tile, index);
+                tilemap.tiles.extend(tile);
+                (index, false, false)
+            });
+
+            let mut word = ((line as u16) << 13) | (tile_base + index) as u16;
+            if hflip {
+                word |= NAMETABLE_HFLIP;
+            }
+            if vflip {
+                word |= NAMETABLE_VFLIP;
+            }
+            tilemap.words.push(word);
+        }
+    }
+
+    if tile_base + seen.len() > 0x800 {
+        return Err(ImportError::TooManyTiles {
+            count: seen.len(),
+            tile_base,
+        });
+    }
+
+    Ok(tilemap)
+}
+
+pub struct BuildBackground {}
+
+impl BuildBackground {
+    #[allow(clippy::too_many_arguments)]
+    pub fn run(
+        input_filename: &str,
+        tiles_filename: &str,
+        mapping_filename: &str,
+        check_newer: bool,
+        compressed: bool,
+        allow_flips: bool,
+        tile_base: usize,
+        palette_filenames: &[&str],
+        color_mapping: ColorMapping,
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(input_filename, mapping_filename, check_newer)?
+            && crate::check_output_newer(input_filename, tiles_filename, check_newer)?
+        {
+            return Ok(());
+        }
+
+        let colors = load_background_colors(palette_filenames, color_mapping)?;
+
+        let import_error = |source| PMDTError::Import {
+            path: input_filename.into(),
+            source,
+        };
+        let image = crate::png::decode(&crate::read_file(input_filename)?)
+            .map_err(|error| import_error(ImportError::Png(error)))?;
+        let tilemap = build_tilemap(
+            &image,
+            (!colors.is_empty()).then_some(colors.as_slice()),
+            color_mapping,
+            tile_base,
+            allow_flips,
+        )
+        .map_err(import_error)?;
+        eprintln!(
+            "[MSG] {} tile(s) in the image, 0x{:X} unique.",
+            tilemap.words.len(),
+            tilemap.tiles.len() / TILE_SIZE
+        );
+
+        // Write the mapping, warning if its type can't hold everything.
+        let mut mapping = Mapping::new(mapping_filename, 0, true, false)?;
+        if tilemap.words.iter().any(|word| mapping.is_lossy(*word)) {
+            eprintln!("[WARN] Lossy background mapping conversion detected!");
+        }
+        for word in tilemap.words.iter() {
+            mapping.write(*word);
+        }
+        mapping.save()?;
+
+        crate::write_file(
+            tiles_filename,
+            &crate::compress_tiles(tilemap.tiles, compressed, input_filename)?,
+        )
+    }
+}
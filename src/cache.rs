@@ -0,0 +1,180 @@
+use crate::PMDTError;
+use crate::depfile::Touched;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Hashes of the files a command read and wrote the last time it ran.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Entry {
+    inputs: Vec<(u64, PathBuf)>,
+    outputs: Vec<(u64, PathBuf)>,
+}
+
+// Remembers the contents of the files each command used, so a command can be skipped when running
+// it again would give the same result.  Unlike --if-newer, this doesn't depend on modification
+// times, which a git checkout shuffles.
+//
+// Commands are told apart by a key, which should hold everything that changes the output other
+// than file contents, such as the command name, its options and its file names.
+//
+// Many processes can share one cache file.  Saving locks the file, merges in the entries this
+// process recorded with whatever the others saved in the meantime, and replaces the file in one
+// step, so a reader never sees it half written.
+pub struct Cache {
+    filename: String,
+    entries: Mutex<BTreeMap<String, Entry>>,
+    // Entries recorded by this process, which are the only ones it saves.
+    updated: Mutex<BTreeMap<String, Entry>>,
+}
+
+// 64-bit FNV-1a.  This only needs to notice files changing, and must give the same hash on every
+// platform and version, which std's hashers don't promise.
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+// Hashes a file, or returns None if it can't be read.  This isn't recorded in depfiles.
+fn hash_file(filename: &Path) -> Option<u64> {
+    std::fs::read(filename).ok().map(|data| hash(&data))
+}
+
+impl Cache {
+    // Loads a cache file.  A cache that doesn't exist yet is empty.
+    pub fn load(filename: &str) -> Result<Cache, PMDTError> {
+        Ok(Cache {
+            filename: filename.to_string(),
+            entries: Mutex::new(read_entries(filename)?),
+            updated: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    // Checks if the command with this key ran before, and every file it read and wrote is still
+    // the same.
+    pub fn is_fresh(&self, key: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.get(key).is_some_and(|entry| {
+            !entry.outputs.is_empty()
+                && entry
+                    .inputs
+                    .iter()
+                    .chain(entry.outputs.iter())
+                    .all(|(digest, path)| hash_file(path) == Some(*digest))
+        })
+    }
+
+    // Records the files a command touched, as they are now that it has finished.  A file that was
+    // both read and written, such as a rom fixed in place, is only recorded as an output.
+    pub fn update(&self, key: &str, touched: &Touched) -> Result<(), PMDTError> {
+        let hash_all = |paths: &mut dyn Iterator<Item = &PathBuf>| {
+            paths
+                .map(|path| {
+                    std::fs::read(path)
+                        .map(|data| (hash(&data), path.clone()))
+                        .map_err(|source| PMDTError::Io {
+                            path: path.clone(),
+                            source,
+                        })
+                })
+                .collect::<Result<Vec<_>, PMDTError>>()
+        };
+        let entry = Entry {
+            inputs: hash_all(
+                &mut touched
+                    .inputs
+                    .iter()
+                    .filter(|input| !touched.outputs.contains(input)),
+            )?,
+            outputs: hash_all(&mut touched.outputs.iter())?,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), entry.clone());
+        self.updated.lock().unwrap().insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    // Writes the entries this process recorded back to the file it was loaded from, keeping any
+    // that other processes saved since then.  Other processes saving to the same file wait for
+    // this one to finish.
+    pub fn save(&self) -> Result<(), PMDTError> {
+        let updated = self.updated.lock().unwrap();
+        if updated.is_empty() {
+            return Ok(());
+        }
+        let path = Path::new(&self.filename);
+        if let Some(prefix) = path.parent() {
+            std::fs::create_dir_all(prefix).map_err(crate::io_error(&self.filename))?;
+        }
+
+        let lock_filename = format!("{}.lock", self.filename);
+        let lock =
+            std::fs::File::create(&lock_filename).map_err(crate::io_error(&lock_filename))?;
+        lock.lock().map_err(crate::io_error(&lock_filename))?;
+
+        let mut entries = read_entries(&self.filename)?;
+        entries.extend(
+            updated
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.clone())),
+        );
+        let mut text = String::from(
+            "# puyomdtool cache.  Each command is followed by hashes of the files it read and \
+             wrote.\n",
+        );
+        for (key, entry) in entries.iter() {
+            text.push_str(&format!("command {key}\n"));
+            for (digest, path) in entry.inputs.iter() {
+                text.push_str(&format!("in {digest:016X} {}\n", path.display()));
+            }
+            for (digest, path) in entry.outputs.iter() {
+                text.push_str(&format!("out {digest:016X} {}\n", path.display()));
+            }
+        }
+
+        // Renaming replaces the file in one step, unlike writing over it.
+        let temp_filename = format!("{}.{}.tmp", self.filename, std::process::id());
+        std::fs::write(&temp_filename, text).map_err(crate::io_error(&temp_filename))?;
+        std::fs::rename(&temp_filename, path).map_err(crate::io_error(&self.filename))
+    }
+}
+
+// Reads the entries in a cache file.  Lines that can't be read are dropped, which only means their
+// command runs again.
+fn read_entries(filename: &str) -> Result<BTreeMap<String, Entry>, PMDTError> {
+    let mut entries = BTreeMap::new();
+    let text = match std::fs::read_to_string(filename) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(crate::io_error(filename)(e)),
+    };
+
+    let mut key = None;
+    for line in text.lines() {
+        if let Some(command) = line.strip_prefix("command ") {
+            key = Some(command.to_string());
+            entries.insert(command.to_string(), Entry::default());
+            continue;
+        }
+        let mut parts = line.splitn(3, ' ');
+        let (Some(kind), Some(digest), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let (Some(entry), Ok(digest)) = (
+            key.as_ref().and_then(|key| entries.get_mut(key)),
+            u64::from_str_radix(digest, 16),
+        ) else {
+            continue;
+        };
+        match kind {
+            "in" => entry.inputs.push((digest, path.into())),
+            "out" => entry.outputs.push((digest, path.into())),
+            _ => (),
+        }
+    }
+    Ok(entries)
+}
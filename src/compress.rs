@@ -1,61 +1,257 @@
-use std::error::Error;
-use std::option::Option;
+use super::PMDTError;
 
+#[derive(Clone, Copy)]
 enum CompressCommand {
     Run,
     Cache,
 }
 
-pub struct Compress {
-    init_data: Vec<u8>,
-    init_ind: usize,
+// Greedy reproduces the original compressor byte-for-byte.  Best finds the smallest possible
+// output using the same commands, so the stock decompressor can still decode it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressMode {
+    Greedy,
+    Best,
+}
+
+// What to do with input whose length isn't a multiple of 4.  The decompressor's 4-byte buffer
+// drops whatever is left over at the end, so that data can never be decompressed whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnalignedPolicy {
+    // Fail with CompressError::UnalignedInput.
+    Refuse,
+    // Pad the end of the input with this byte until it is a multiple of 4.
+    Pad(u8),
+    // Compress the input as is.
+    Warn,
+}
+
+// Settings for compress_with_options.  verify decompresses the result in memory and checks that
+// it matches the input, and is on by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressOptions {
+    pub mode: CompressMode,
+    pub verify: bool,
+    pub unaligned: UnalignedPolicy,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            mode: CompressMode::Greedy,
+            verify: true,
+            unaligned: UnalignedPolicy::Warn,
+        }
+    }
+}
 
-    compress_buf: [Option<u8>; 0x100],
-    compress_ind: usize,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressError {
+    // Decompressing the compressed data didn't give back the input.  decompressed_len is how much
+    // data came back, and first_difference is the first offset that doesn't match.
+    // unaligned_input is set if the only problem is that the input length isn't a multiple of 4,
+    // so the decompressor's 4-byte buffer dropped the last few bytes.
+    VerifyFailed {
+        input_len: usize,
+        decompressed_len: usize,
+        first_difference: usize,
+        unaligned_input: bool,
+    },
+    // The input length isn't a multiple of 4, and UnalignedPolicy::Refuse was used.
+    UnalignedInput {
+        input_len: usize,
+    },
+}
 
-    compress_buf_bak: [Option<u8>; 0x100],
-    compress_ind_bak: usize,
+impl std::fmt::Display for CompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressError::VerifyFailed {
+                input_len,
+                decompressed_len,
+                unaligned_input: true,
+                ..
+            } => write!(
+                f,
+                "Verification failed: decompressing gave 0x{:X} byte(s) instead of 0x{:X}.  The input \
+                 length isn't a multiple of 4, so the decompressor drops the last {} byte(s).",
+                decompressed_len,
+                input_len,
+                input_len - decompressed_len
+            ),
+            CompressError::VerifyFailed {
+                input_len,
+                decompressed_len,
+                first_difference,
+                ..
+            } => write!(
+                f,
+                "Verification failed: decompressed data differs from the input at offset 0x{:X} \
+                 (got 0x{:X} byte(s), expected 0x{:X}).",
+                first_difference, decompressed_len, input_len
+            ),
+            CompressError::UnalignedInput { input_len } => write!(
+                f,
+                "Input is 0x{:X} byte(s), which isn't a multiple of 4.  The last {} byte(s) \
+                 would be dropped when decompressed.",
+                input_len,
+                input_len % 4
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+pub struct Compress<'a> {
+    init_data: &'a [u8],
+    init_ind: usize,
+
+    // For each position, the previous position with the same byte, and the previous position
+    // whose next 3 bytes have the same hash.  These are used to find matches quickly.
+    prev_byte: Vec<Option<usize>>,
+    prev_triple: Vec<Option<usize>>,
 
     output_buffer: Vec<u8>,
 }
 
-impl Compress {
+// Compresses data in memory and returns the compressed bytes.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compress_instance = Compress::new(data);
+    compress_instance.compress();
+    compress_instance.output_buffer
+}
+
+// Compresses data in memory using the given mode and returns the compressed bytes.
+pub fn compress_with_mode(data: &[u8], mode: CompressMode) -> Vec<u8> {
+    let mut compress_instance = Compress::new(data);
+    match mode {
+        CompressMode::Greedy => compress_instance.compress(),
+        CompressMode::Best => compress_instance.compress_best(),
+    }
+    compress_instance.output_buffer
+}
+
+// Compresses data in memory using the given options.  If verification is turned on, the result is
+// decompressed the same way the game would, and an error is returned if it doesn't match.
+pub fn compress_with_options(
+    data: &[u8],
+    options: &CompressOptions,
+) -> Result<Vec<u8>, CompressError> {
+    // Deal with data that the decompressor can't give back whole.
+    let mut data = std::borrow::Cow::Borrowed(data);
+    if !data.len().is_multiple_of(4) {
+        match options.unaligned {
+            UnalignedPolicy::Refuse => {
+                return Err(CompressError::UnalignedInput {
+                    input_len: data.len(),
+                });
+            }
+            UnalignedPolicy::Pad(val) => {
+                let padded_len = data.len().next_multiple_of(4);
+                data.to_mut().resize(padded_len, val);
+            }
+            UnalignedPolicy::Warn => (),
+        }
+    }
+
+    let output_data = compress_with_mode(&data, options.mode);
+    if options.verify {
+        verify(&data, &output_data)?;
+    }
+    Ok(output_data)
+}
+
+// Checks that compressed decompresses back to original.  If it can't be decoded at all, that's
+// treated as nothing coming back.
+fn verify(original: &[u8], compressed: &[u8]) -> Result<(), CompressError> {
+    let decompressed = crate::decompress::decompress(compressed, false).unwrap_or_default();
+    if decompressed == original {
+        return Ok(());
+    }
+
+    let first_difference = decompressed
+        .iter()
+        .zip(original)
+        .take_while(|(a, b)| a == b)
+        .count();
+    Err(CompressError::VerifyFailed {
+        input_len: original.len(),
+        decompressed_len: decompressed.len(),
+        first_difference,
+        unaligned_input: !original.len().is_multiple_of(4)
+            && decompressed.len() == original.len() & !0x3
+            && first_difference == decompressed.len(),
+    })
+}
+
+impl<'a> Compress<'a> {
     pub fn run(
         input_filename: &str,
         output_filename: &str,
         check_newer: bool,
-    ) -> Result<(), Box<dyn Error>> {
+        options: &CompressOptions,
+    ) -> Result<(), PMDTError> {
         if crate::check_output_newer(input_filename, output_filename, check_newer)? {
             return Ok(());
         }
 
         // Run Compression
-        let mut compress_instance = Compress::new(input_filename)?;
-        compress_instance.compress();
-
-        // Create output directory path if it doesn't exist, and write the file.
-        let path = std::path::Path::new(output_filename);
-        let prefix = path
-            .parent()
-            .ok_or("Getting directory path of file failed!")?;
-        std::fs::create_dir_all(prefix)?;
-        std::fs::write(output_filename, compress_instance.output_buffer)?;
-        Ok(())
+        let input_data = crate::read_file(input_filename)?;
+
+        // Let the user know what happened if the input can't be decompressed whole.
+        let leftover = input_data.len() % 4;
+        match options.unaligned {
+            _ if leftover == 0 => (),
+            UnalignedPolicy::Refuse => (),
+            UnalignedPolicy::Pad(val) => eprintln!(
+                "[MSG] Input is 0x{:X} byte(s), padded with {} byte(s) of 0x{:02X}.",
+                input_data.len(),
+                4 - leftover,
+                val
+            ),
+            UnalignedPolicy::Warn => eprintln!(
+                "[WARN] Input is 0x{:X} byte(s), which isn't a multiple of 4.  The last {} \
+                 byte(s) will be dropped when decompressed!",
+                input_data.len(),
+                leftover
+            ),
+        }
+
+        let output_data =
+            compress_with_options(&input_data, options).map_err(|source| PMDTError::Compress {
+                path: input_filename.into(),
+                source,
+            })?;
+
+        crate::write_file(output_filename, &output_data)
     }
 
-    fn new(input_filename: &str) -> Result<Self, Box<dyn Error>> {
-        Ok(Self {
-            init_data: std::fs::read(input_filename)?,
-            init_ind: 0,
+    fn new(init_data: &'a [u8]) -> Self {
+        // Link up every position with the last one that started the same way.
+        let mut prev_byte = vec![None; init_data.len()];
+        let mut prev_triple = vec![None; init_data.len()];
+        let mut last_byte = [None; 0x100];
+        let mut last_triple = vec![None; 0x10000];
+        for (pos, window) in init_data.windows(3).enumerate() {
+            let hash = (((window[0] as u32) << 16 | (window[1] as u32) << 8 | window[2] as u32)
+                .wrapping_mul(0x9E3779B1)
+                >> 16) as usize;
+            prev_triple[pos] = last_triple[hash].replace(pos);
+        }
+        for (pos, byte) in init_data.iter().enumerate() {
+            prev_byte[pos] = last_byte[*byte as usize].replace(pos);
+        }
 
-            compress_buf: [Option::None; 0x100],
-            compress_ind: 0,
+        Self {
+            init_data,
+            init_ind: 0,
 
-            compress_buf_bak: [Option::None; 0x100],
-            compress_ind_bak: 0,
+            prev_byte,
+            prev_triple,
 
             output_buffer: Vec::new(),
-        })
+        }
     }
 
     // Function to read data from the decompressed file
@@ -70,94 +266,66 @@ impl Compress {
         self.output_buffer.push(val);
     }
 
-    // Takes in an array and checks to see if it can be found inside the compression buffer.
-    // Returns Some(index) if it is found, and None if it isn't.
-    fn find_in_buf(&mut self, a: &[u8]) -> Option<i32> {
-        // Do a backup of the buffer before we touch anything.  We'll be modifying it a lot on the
-        // fly here, and we need to be able to revert the changes later.
-        self.backup_buf();
-
-        // Start at the current index into the compression buffer.
-        let mut i: u16 = self.compress_ind_bak as u16;
-
-        // Loop until we've looped all the way around to the original value again
-        'main_loop: while i < (0x100 | self.compress_ind_bak as u16) {
-            // Checks for an intended race condition where the data in the beginning of a can be
-            // detected in compress_buf.
-            let race_condition = i + a.len() as u16 >= (0x100 | self.compress_ind_bak as u16);
-
-            // Reload the original buffer only if we're hitting the race condition (optimization)
-            if race_condition {
-                self.restore_buf();
-            }
-
-            // Loop for the length of the inputted array.
-            for (pos, cur_byte) in a.iter().enumerate() {
-                match self.read_buf(i as usize + pos) {
-                    // If the index contains an initialized value, check to see if it's the
-                    // next value we expect.  If so, update the compression buffer, and if not,
-                    // continue to the next index.
-                    Some(val) if val == *cur_byte => {
-                        // Only do this if we're hitting the race condition (optimization)
-                        if race_condition {
-                            self.write_buf(val);
-                        }
-                    }
-                    _ => {
-                        // The value grabbed from the decompression buffer wasn't initialized, or
-                        // wasn't the next value we expect, so go to the next index.
-                        i += 1;
-                        continue 'main_loop;
-                    }
-                }
-            }
-
-            // Restore changes to the buffer and return the index at which the inputted array was
-            // first found.
-            self.restore_buf();
-            return Some((i & 0xFF) as i32);
-        }
-
-        // Inputted array was not found, so restore the changes to the buffer and return None.
-        self.restore_buf();
-        None
-    }
-
-    // Read from the compression buffer
-    fn read_buf(&self, ind: usize) -> Option<u8> {
-        self.compress_buf[ind & 0xFF]
-    }
-
-    // Write to the compression buffer
-    fn write_buf(&mut self, val: u8) {
-        self.compress_buf[self.compress_ind] = Option::Some(val);
-        self.compress_ind += 1;
-        self.compress_ind &= 0xFF;
+    // The compression buffer always holds the last 0x100 bytes of input before the current
+    // position, so rather than keeping a copy of it, matches are checked against the input itself.
+    // A match is allowed to run past the position it's copied to, since the decompressor writes
+    // each byte into the buffer before it reads the next one.  Data before the start of the input
+    // was never written to the buffer, so it can't be matched.
+    //
+    // Walks the candidate positions that could match len bytes at pos, from newest to oldest, and
+    // returns the distance back to each one that actually matches.
+    fn buf_matches(&self, pos: usize, len: usize) -> impl Iterator<Item = usize> + '_ {
+        let chain = if len >= 3 {
+            &self.prev_triple
+        } else {
+            &self.prev_byte
+        };
+        std::iter::successors(chain[pos], move |cand| chain[*cand])
+            .take_while(move |cand| pos - cand <= 0x100)
+            .filter(move |cand| {
+                self.init_data[*cand..*cand + len] == self.init_data[pos..pos + len]
+            })
+            .map(move |cand| pos - cand)
     }
 
-    // Backup the compression buffer
-    fn backup_buf(&mut self) {
-        self.compress_buf_bak.copy_from_slice(&self.compress_buf);
-        self.compress_ind_bak = self.compress_ind;
+    // Checks to see if the len bytes at pos can be found inside the compression buffer.
+    fn found_in_buf(&self, pos: usize, len: usize) -> bool {
+        self.buf_matches(pos, len).next().is_some()
     }
 
-    // Restore the compression buffer
-    fn restore_buf(&mut self) {
-        self.compress_buf.copy_from_slice(&self.compress_buf_bak);
-        self.compress_ind = self.compress_ind_bak;
+    // Finds the longest match in the compression buffer for the data at pos, up to max_len bytes.
+    // If several matches are just as long, the oldest one is used, since that's the first one the
+    // original compressor would find.  Returns the length and distance back to the match.
+    fn find_longest_in_buf(&self, pos: usize, max_len: usize) -> Option<(usize, usize)> {
+        // Check the oldest matches first, so we can stop as soon as one can't be beaten.
+        let matches: Vec<usize> = self.buf_matches(pos, 3).collect();
+        let mut best: Option<(usize, usize)> = None;
+        for dist in matches.into_iter().rev() {
+            let match_len = self.init_data[pos - dist..]
+                .iter()
+                .zip(&self.init_data[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if best.is_none_or(|(best_len, _)| match_len > best_len) {
+                best = Some((match_len, dist));
+                if match_len == max_len {
+                    break;
+                }
+            }
+        }
+        best
     }
 
-    // Determine what the next command in the compressed file will be.  v2 will always contain one
-    // byte when the function starts.
-    fn determine_next_command(&mut self, v2: &[u8]) -> CompressCommand {
-        // Check to see if the data in v2 can be found in the buffer.
-        let mut v3 = v2.to_vec();
-        if self.find_in_buf(&v3).is_none() {
+    // Determine what the next command in the compressed file will be, starting from the current
+    // position in the input.
+    fn determine_next_command(&self) -> CompressCommand {
+        // Check to see if the next byte can be found in the buffer.
+        if !self.found_in_buf(self.init_ind, 1) {
             // If the data cannot be found, do a run
             CompressCommand::Run
         } else {
-            // The byte in v2 can be found, so now check to see if there is enough uncompressed
-            // data left for a chche command.  (Cache commands have a minimum length of 3 bytes.)
+            // The byte can be found, so now check to see if there is enough uncompressed data
+            // left for a cache command.  (Cache commands have a minimum length of 3 bytes.)
             if self.init_ind + 2 >= self.init_data.len() {
                 // Ran out of data, do a run for the rest.
                 return CompressCommand::Run;
@@ -165,9 +333,7 @@ impl Compress {
 
             // If the data could be found, and the bounds check passed, then do an initial check to
             // see if the next two bytes along with this one are found in the table.
-            v3.push(self.init_data[self.init_ind + 1]);
-            v3.push(self.init_data[self.init_ind + 2]);
-            if self.find_in_buf(&v3).is_some() {
+            if self.found_in_buf(self.init_ind, 3) {
                 // If they can be found, end the run and initiate a normal cache command.
                 CompressCommand::Cache
             } else {
@@ -185,18 +351,14 @@ impl Compress {
 
         // While there is still data left to compress
         while self.init_ind < self.init_data.len() {
-            // Initialize two vectors for later usage
-            let mut v: Vec<u8> = Vec::new();
-            let mut v2: Vec<u8> = Vec::new();
-
             // Run code depending on the next command
             match next_command {
                 CompressCommand::Run => {
+                    let mut v: Vec<u8> = Vec::new();
                     loop {
-                        // Push a byte into V and the buffer (at this point, this byte is confirmed
-                        // not to be part of a cache command)
+                        // Push a byte into V (at this point, this byte is confirmed not to be part
+                        // of a cache command)
                         v.push(self.read_init());
-                        self.write_buf(v[v.len() - 1]);
 
                         // Bounds check on file length and run command length
                         // (Run commands have a maximum length of 0x7F)
@@ -205,15 +367,8 @@ impl Compress {
                             break;
                         }
 
-                        // Grab the next byte
-                        let next_byte = self.init_data[self.init_ind];
-
-                        // Check to see if the byte can match something in the table.
-                        v2 = Vec::new();
-                        v2.push(next_byte);
-
                         // If we're still doing a run command, loop.  Otherwise, break out.
-                        next_command = self.determine_next_command(&v2);
+                        next_command = self.determine_next_command();
                         if let CompressCommand::Run = next_command {
                             continue;
                         } else {
@@ -228,86 +383,108 @@ impl Compress {
                     }
                 }
                 CompressCommand::Cache => {
-                    // Entering this assumes we found at least one byte in the table.
-
-                    // First, pull one byte.
-                    v.push(self.read_init());
+                    // Entering this assumes we found at least three bytes in the table.  Take as
+                    // many bytes as possible, up to the end of the data.  Cache commands have a
+                    // maximum length of 0x82.
+                    let max_len = (self.init_data.len() - self.init_ind).min(0x82);
+                    let (len, dist) = self
+                        .find_longest_in_buf(self.init_ind, max_len)
+                        .expect("a cache command is only picked when a 3 byte match was found");
+                    self.init_ind += len;
 
-                    // Define a bool that lets us break out of this loop without removing an extra
-                    // value from the buffer.
-                    let mut remove_value = true;
-
-                    // Next, we loop until we can no longer match something in the table.
-                    loop {
-                        // Bounds Check
-                        if self.init_ind >= self.init_data.len() {
-                            // Time to end the cache.  Since we end unexpectedly, we don't want to
-                            // remove a value from the array of bytes that the cache uses.
-                            remove_value = false;
-                            break;
-                        }
+                    // Next, we need to construct the command.
+                    self.write_out(0x80 | (len as u8 - 3)); // Command | (Length - 3)
 
-                        // Load the next byte onto the cache run
-                        v.push(self.read_init());
+                    // The index byte is the distance back to the found data, minus 1.
+                    self.write_out((dist - 1) as u8);
 
-                        // If this array of values doesn't exist in the cache, break out of the
-                        // loop.
-                        if self.find_in_buf(&v).is_none() {
-                            break;
-                        }
-
-                        // Bounds Check
-                        if self.init_ind >= self.init_data.len() {
-                            // Time to end the cache.
-                            remove_value = false;
-                            break;
-                        }
-
-                        // Cache commands have a maximum length of 0x82
-                        if v.len() >= 0x82 {
-                            // Time to end the cache.
-                            remove_value = false;
-                            break;
-                        }
+                    // Bounds check
+                    if self.init_ind >= self.init_data.len() {
+                        // Quit to the main loop.
+                        break;
                     }
 
-                    // Here, V contains one too many elements currently (if we didn't exit
-                    // unexpectedly), since it still contains the incorrect byte at the end so we
-                    // remove it.
-                    if remove_value {
-                        v.remove(v.len() - 1);
-                        self.init_ind -= 1;
-                    }
+                    // All that's left to do is find the next command.
+                    next_command = self.determine_next_command();
+                }
+            }
+        }
 
-                    // Grab the index of the found array
-                    let Some(ind) = self.find_in_buf(&v) else {
-                        // Original code didn't account for this?  I don't think this can happen,
-                        // need to double check.
-                        panic!("Unreachable?  Open an issue on GitHub if you see this.");
-                    };
+        // End the file with an end command
+        self.write_out(0);
+    }
 
-                    // Next, we need to construct the command.
-                    self.write_out(0x80 | (v.len() as u8 - 3)); // Command | (Length - 3)
+    // Compresses the data using optimal parsing.  Every position works out the cheapest way to
+    // encode everything after it, then the cheapest path is written out from the start.  This
+    // sticks to the same limits as the greedy compressor (runs of up to 0x7F bytes, caches of
+    // 3 to 0x82 bytes from up to 0x100 bytes back), so the output decodes the same way.
+    pub fn compress_best(&mut self) {
+        let len = self.init_data.len();
+
+        // cost[i] is the number of bytes needed to encode everything from i onwards (not counting
+        // the end command), and choice[i] is the command that achieves it.
+        let mut cost = vec![0usize; len + 1];
+        let mut choice = vec![(CompressCommand::Run, 0usize, 0usize); len];
+
+        // match_len[d] is how many bytes starting at the current position match the bytes d
+        // positions earlier.  Matches are allowed to overlap the data being written, just like
+        // they can in the decompression buffer.
+        let mut match_len = [0usize; 0x101];
+
+        for i in (0..len).rev() {
+            // Update the match lengths for this position, and find the longest one.
+            let mut best_len = 0;
+            let mut best_dist = 0;
+            for (dist, cur_len) in match_len.iter_mut().enumerate().skip(1) {
+                *cur_len = if dist <= i && self.init_data[i] == self.init_data[i - dist] {
+                    (*cur_len + 1).min(0x82)
+                } else {
+                    0
+                };
+                if *cur_len > best_len {
+                    best_len = *cur_len;
+                    best_dist = dist;
+                }
+            }
 
-                    // The index byte is determined by taking the original cache index and
-                    // subtracting it by the found index and then also subtracting by 1.
-                    self.write_out((self.compress_ind as u8 - ind as u8) - 1);
+            // Try every length of run command.  Ties go to the longest run, so like the greedy
+            // compressor, a run is only cut short when a cache command or the end comes next.
+            let mut best = (usize::MAX, CompressCommand::Run, 0, 0);
+            for run_len in 1..=0x7F.min(len - i) {
+                let run_cost = 1 + run_len + cost[i + run_len];
+                if run_cost <= best.0 {
+                    best = (run_cost, CompressCommand::Run, run_len, 0);
+                }
+            }
 
-                    // Write the cache loaded bytes to the compression buffer.
-                    for h in v.iter() {
-                        self.write_buf(*h);
-                    }
+            // Try every length of cache command.  The longest match can be cut short to any
+            // length, so the same distance works for all of them.
+            for cache_len in 3..=best_len {
+                let cache_cost = 2 + cost[i + cache_len];
+                if cache_cost < best.0 {
+                    best = (cache_cost, CompressCommand::Cache, cache_len, best_dist);
+                }
+            }
 
-                    // Bounds check
-                    if self.init_ind >= self.init_data.len() {
-                        // Quit to the main loop.
-                        break;
-                    }
+            cost[i] = best.0;
+            choice[i] = (best.1, best.2, best.3);
+        }
 
-                    // The cache buffer is already updated, so all that's left to do is find the
-                    // next command.
-                    v2.push(self.init_data[self.init_ind]);
-                    next_command = self.determine_next_command(&v2);
+        // Write out the commands along the cheapest path.
+        while self.init_ind < len {
+            let (command, cmd_len, dist) = choice[self.init_ind];
+            match command {
+                CompressCommand::Run => {
+                    self.write_out(cmd_len as u8);
+                    for _ in 0..cmd_len {
+                        let val = self.read_init();
+                        self.write_out(val);
+                    }
+                }
+                CompressCommand::Cache => {
+                    self.write_out(0x80 | (cmd_len as u8 - 3)); // Command | (Length - 3)
+                    self.write_out((dist - 1) as u8);
+                    self.init_ind += cmd_len;
                 }
             }
         }
@@ -1,13 +1,13 @@
-use std::error::Error;
+use super::PMDTError;
 use std::option::Option;
 
-enum MappingType {
+pub(crate) enum MappingType {
     Byte,
     Pal,
     Word,
 }
 
-struct Mapping {
+pub(crate) struct Mapping {
     filename: String,
 
     map_data: Vec<u8>,
@@ -28,15 +28,19 @@ impl Mapping {
         common_word: u16,
         is_output: bool,
         force_word: bool,
-    ) -> Result<Self, Box<dyn Error>> {
-        let map_type = if filename.ends_with("bgword") || force_word {
+    ) -> Result<Self, PMDTError> {
+        // stdin and stdout have no extension to go by, and can't have a bgpalp file alongside them,
+        // so they are always bgword.
+        let map_type = if filename.ends_with("bgword") || force_word || filename == crate::STDIO {
             MappingType::Word
         } else if filename.ends_with("bgpalm") {
             MappingType::Pal
         } else if filename.ends_with("bgbyte") {
             MappingType::Byte
         } else {
-            return Err(Box::new(super::PMDTError::UnknownMappingType));
+            return Err(PMDTError::UnknownMappingType {
+                path: filename.into(),
+            });
         };
 
         let mapping = if !is_output {
@@ -45,9 +49,9 @@ impl Mapping {
             palp_string.push('p');
             Mapping {
                 filename: filename.to_string(),
-                map_data: std::fs::read(filename)?,
+                map_data: crate::read_file(filename)?,
                 pal_data: if let MappingType::Pal = map_type {
-                    std::fs::read(palp_string)?
+                    crate::read_file(&palp_string)?
                 } else {
                     Vec::new()
                 },
@@ -71,15 +75,23 @@ impl Mapping {
         };
 
         // Do some validity checks.  If the mapping is an output, these checks will still pass.
-        if let MappingType::Pal = mapping.map_type {
-            if mapping.map_data.len() != mapping.pal_data.len() * 4 {
-                return Err(Box::new(super::PMDTError::WrongMappingSize));
-            }
+        if let MappingType::Pal = mapping.map_type
+            && mapping.map_data.len() != mapping.pal_data.len() * 4
+        {
+            return Err(PMDTError::WrongMappingSize {
+                path: filename.into(),
+                expected: mapping.pal_data.len() * 4,
+                actual: mapping.map_data.len(),
+            });
         }
-        if let MappingType::Word = mapping.map_type {
-            if mapping.map_data.len() & 0x1 != 0 {
-                return Err(Box::new(super::PMDTError::WrongMappingSize));
-            }
+        if let MappingType::Word = mapping.map_type
+            && mapping.map_data.len() & 0x1 != 0
+        {
+            return Err(PMDTError::WrongMappingSize {
+                path: filename.into(),
+                expected: mapping.map_data.len() + 1,
+                actual: mapping.map_data.len(),
+            });
         }
 
         Ok(mapping)
@@ -136,15 +148,18 @@ impl Mapping {
         }
     }
 
+    // Checks if val has any bits set that this mapping type can't store.
+    pub fn is_lossy(&self, val: u16) -> bool {
+        match self.map_type {
+            MappingType::Pal => val & 0x9F00 != 0,
+            MappingType::Byte => val & 0xFF00 != 0,
+            MappingType::Word => false,
+        }
+    }
+
     // Save the background mapping file.
-    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
-        // Create output directory path if it doesn't exist, and write the file.
-        let path = std::path::Path::new(&self.filename);
-        let prefix = path
-            .parent()
-            .ok_or("Getting directory path of file failed!")?;
-        std::fs::create_dir_all(prefix)?;
-        std::fs::write(&self.filename, &self.map_data)?;
+    pub fn save(&mut self) -> Result<(), PMDTError> {
+        crate::write_file(&self.filename, &self.map_data)?;
 
         // Write the Pal file, if it is needed.
         if let MappingType::Pal = self.map_type {
@@ -157,7 +172,7 @@ impl Mapping {
                 self.pal_data.push(self.pal_queue);
             }
 
-            std::fs::write(palp_string, &self.pal_data)?;
+            crate::write_file(&palp_string, &self.pal_data)?;
         }
 
         Ok(())
@@ -178,7 +193,7 @@ impl Convert {
         force_word_in: bool,
         force_word_out: bool,
         common_word: u16,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), PMDTError> {
         if crate::check_output_newer(input_filename, output_filename, check_newer)? {
             return Ok(());
         }
@@ -201,7 +216,7 @@ impl Convert {
         force_word_in: bool,
         force_word_out: bool,
         common_word: u16,
-    ) -> Result<Self, Box<dyn Error>> {
+    ) -> Result<Self, PMDTError> {
         Ok(Self {
             input_mapping: Mapping::new(input_filename, common_word, false, force_word_in)?,
             output_mapping: Mapping::new(output_filename, 0, true, force_word_out)?,
@@ -211,17 +226,15 @@ impl Convert {
 
     fn print_lossy_conv_warning(&mut self) {
         if !self.lossy_conversion_warning {
-            println!("[WARN] Lossy background mapping conversion detected!");
+            eprintln!("[WARN] Lossy background mapping conversion detected!");
             self.lossy_conversion_warning = true;
         }
     }
 
     fn convert(&mut self) {
         while let Some(val) = self.input_mapping.read() {
-            match self.output_mapping.map_type {
-                MappingType::Pal if val & 0x9F00 != 0 => self.print_lossy_conv_warning(),
-                MappingType::Byte if val & 0xFF00 != 0 => self.print_lossy_conv_warning(),
-                _ => (),
+            if self.output_mapping.is_lossy(val) {
+                self.print_lossy_conv_warning();
             }
             self.output_mapping.write(val);
         }
@@ -1,7 +1,59 @@
-use std::error::Error;
+use super::PMDTError;
 
-pub struct Decompress {
-    init_data: Vec<u8>,
+// Describes what was wrong with a compressed stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressErrorKind {
+    // The data ended before a 0x00 end command was found.
+    MissingEndCommand,
+    // The data ended in the middle of a run command.
+    TruncatedRun,
+    // The data ended before the offset byte of a cache command.
+    MissingCacheOffset,
+    // A cache command read part of the decompression buffer that was never written.  This is
+    // only checked when decompressing in strict mode.
+    UninitializedCacheRead,
+    // A run command came straight after one shorter than the 0x7F byte limit.  Compressors only
+    // cut a run short when a cache command or the end comes next.  This is only checked when
+    // decompressing in strict mode.
+    SplitRun,
+    // The data decoded to more bytes than the limit given for it.
+    TooLarge,
+}
+
+// Returned when a compressed stream can't be decoded.  offset is the position in the input of the
+// command that couldn't be completed, and output_len is the number of bytes that were decoded
+// before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecompressError {
+    pub kind: DecompressErrorKind,
+    pub offset: usize,
+    pub output_len: usize,
+}
+
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self.kind {
+            DecompressErrorKind::MissingEndCommand => "data ended without an end command",
+            DecompressErrorKind::TruncatedRun => "data ended in the middle of a run command",
+            DecompressErrorKind::MissingCacheOffset => "cache command is missing its offset byte",
+            DecompressErrorKind::UninitializedCacheRead => {
+                "cache command reads an unwritten part of the buffer"
+            }
+            DecompressErrorKind::SplitRun => "run command follows a run that was cut short",
+            DecompressErrorKind::TooLarge => "data decodes to more than the size limit",
+        };
+        write!(
+            f,
+            "Bad compressed data at offset 0x{:X}: {} (0x{:X} byte(s) decoded)",
+            self.offset, reason, self.output_len
+        )
+    }
+}
+
+impl std::error::Error for DecompressError {}
+
+pub struct Decompress<'a> {
+    init_data: &'a [u8],
     init_ind: usize,
 
     output_pre_buf: Vec<u8>,
@@ -11,39 +63,176 @@ pub struct Decompress {
 
     decompress_buf: Vec<u8>,
     decompress_ind: u8,
+    decompress_written: usize,
+    strict: bool,
+    max_output: usize,
+
+    commands: Option<Vec<Command>>,
 
     cmd: u32,
 }
 
-impl Decompress {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    Run,
+    Cache,
+    End,
+}
+
+// A single command from a compressed stream.  offset is the position of the command byte in the
+// input, and output_start is the position of the first byte it produces in the decoded data (not
+// counting the 4-byte buffer, so this is the same with or without nobuf).  distance is how far
+// back a cache command copies from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub offset: usize,
+    pub kind: CommandKind,
+    pub length: usize,
+    pub distance: Option<usize>,
+    pub output_start: usize,
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.kind {
+            CommandKind::Run => "run",
+            CommandKind::Cache => "cache",
+            CommandKind::End => "end",
+        };
+        write!(
+            f,
+            "0x{:06X}  {:<5}  len 0x{:02X}",
+            self.offset, name, self.length
+        )?;
+        match self.distance {
+            Some(distance) => write!(f, "  dist 0x{:03X}", distance)?,
+            None => write!(f, "  dist -----")?,
+        }
+        if self.length != 0 {
+            write!(
+                f,
+                "  out 0x{:06X}-0x{:06X}",
+                self.output_start,
+                self.output_start + self.length - 1
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Every command decoded from a stream.  If decoding failed, commands holds everything up to the
+// point where it broke.
+pub struct Trace {
+    pub commands: Vec<Command>,
+    pub error: Option<DecompressError>,
+}
+
+// Decodes the block starting at offset and records each command in it.
+pub fn trace(data: &[u8], offset: usize) -> Trace {
+    let mut decompress_instance = Decompress::new(data, offset, true, false);
+    decompress_instance.commands = Some(Vec::new());
+    let error = decompress_instance.decompress().err();
+    Trace {
+        commands: decompress_instance.commands.unwrap_or_default(),
+        error,
+    }
+}
+
+// A block of compressed data that was decoded from inside a larger file.
+pub struct DecompressedBlock {
+    pub data: Vec<u8>,
+    // Number of input bytes the block took up, including the end command.
+    pub compressed_len: usize,
+    // Number of trailing bytes that were dropped by the 4-byte output buffer.
+    pub discarded: usize,
+}
+
+// Decompresses data in memory and returns the decompressed bytes.  If nobuf is set, the 4-byte
+// output buffer is disabled and no trailing data will be discarded.
+pub fn decompress(data: &[u8], nobuf: bool) -> Result<Vec<u8>, DecompressError> {
+    Ok(decompress_at(data, 0, nobuf)?.data)
+}
+
+// Decompresses the block starting at offset inside data (for example, a whole rom), stopping at
+// the block's end command.
+pub fn decompress_at(
+    data: &[u8],
+    offset: usize,
+    nobuf: bool,
+) -> Result<DecompressedBlock, DecompressError> {
+    Decompress::new(data, offset, nobuf, false).decompress_block()
+}
+
+// Same as decompress_at, but also fails if a cache command reads from a part of the decompression
+// buffer that hasn't been written yet, or a run command follows a run shorter than the limit.
+// Real compressed data never does this, so it is a good way to reject data that only happens to
+// decode.
+pub fn decompress_at_strict(
+    data: &[u8],
+    offset: usize,
+    nobuf: bool,
+) -> Result<DecompressedBlock, DecompressError> {
+    Decompress::new(data, offset, nobuf, true).decompress_block()
+}
+
+// Same as decompress_at_strict, but gives up as soon as more than max_output bytes have been
+// decoded, rather than following data that isn't compressed until it happens to hit a 0x00.
+pub fn decompress_at_limited(
+    data: &[u8],
+    offset: usize,
+    nobuf: bool,
+    max_output: usize,
+) -> Result<DecompressedBlock, DecompressError> {
+    let mut decompress_instance = Decompress::new(data, offset, nobuf, true);
+    decompress_instance.max_output = max_output;
+    decompress_instance.decompress_block()
+}
+
+impl<'a> Decompress<'a> {
     pub fn run(
         input_filename: &str,
         output_filename: &str,
         check_newer: bool,
         nobuf: bool,
-    ) -> Result<(), Box<dyn Error>> {
+        offset: Option<usize>,
+    ) -> Result<(), PMDTError> {
         if crate::check_output_newer(input_filename, output_filename, check_newer)? {
             return Ok(());
         }
 
         // Run Decompression
-        let mut decompress_instance = Decompress::new(input_filename, nobuf)?;
-        decompress_instance.decompress();
-
-        // Create output directory path if it doesn't exist, and write the file.
-        let path = std::path::Path::new(output_filename);
-        let prefix = path
-            .parent()
-            .ok_or("Getting directory path of file failed!")?;
-        std::fs::create_dir_all(prefix)?;
-        std::fs::write(output_filename, decompress_instance.output_buffer)?;
-        Ok(())
+        let input_data = crate::read_file(input_filename)?;
+        let block = decompress_at(&input_data, offset.unwrap_or(0), nobuf).map_err(|source| {
+            PMDTError::Decompress {
+                path: input_filename.into(),
+                source,
+            }
+        })?;
+
+        if block.discarded != 0 {
+            eprintln!(
+                "[WARN] {} byte(s) discarded when decompressed!",
+                block.discarded
+            );
+        }
+
+        // When pulling a block out of a larger file, report where it ends.
+        if let Some(offset) = offset {
+            eprintln!(
+                "[MSG] Compressed block at 0x{:X} is 0x{:X} byte(s) long (ends at 0x{:X})",
+                offset,
+                block.compressed_len,
+                offset + block.compressed_len
+            );
+        }
+
+        crate::write_file(output_filename, &block.data)
     }
 
-    fn new(input_filename: &str, nobuf: bool) -> Result<Self, Box<dyn Error>> {
-        Ok(Self {
-            init_data: std::fs::read(input_filename)?,
-            init_ind: 0,
+    fn new(init_data: &'a [u8], init_ind: usize, nobuf: bool, strict: bool) -> Self {
+        Self {
+            init_data,
+            init_ind,
 
             output_pre_buf: vec![0; 0x4],
             output_pre_ind: 0,
@@ -52,22 +241,53 @@ impl Decompress {
 
             decompress_buf: vec![0; 0x100],
             decompress_ind: 0,
+            decompress_written: 0,
+            strict,
+            max_output: usize::MAX,
+
+            commands: None,
 
             cmd: 0,
+        }
+    }
+
+    // Runs the decompression and packages up the result.
+    fn decompress_block(mut self) -> Result<DecompressedBlock, DecompressError> {
+        let offset = self.init_ind;
+        self.decompress()?;
+        Ok(DecompressedBlock {
+            compressed_len: self.init_ind - offset,
+            discarded: if self.output_pre_disable {
+                0
+            } else {
+                self.output_pre_ind
+            },
+            data: self.output_buffer,
         })
     }
 
-    // Function to read data from the compressed file
-    fn read_init(&mut self) -> u8 {
-        let a = self.init_data[self.init_ind];
+    // Function to read data from the compressed file.  Returns None if there is no data left.
+    fn read_init(&mut self) -> Option<u8> {
+        let a = *self.init_data.get(self.init_ind)?;
         self.init_ind += 1;
-        a
+        Some(a)
+    }
+
+    // Number of bytes that have been decoded so far, including any still held in the 4-byte
+    // buffer.
+    fn output_len(&self) -> usize {
+        if self.output_pre_disable {
+            self.output_buffer.len()
+        } else {
+            self.output_buffer.len() + self.output_pre_ind
+        }
     }
 
     // Writes to the decompression buffer
     fn write_buf(&mut self, val: u8) {
         self.decompress_buf[self.decompress_ind as usize] = val;
-        self.decompress_ind += 1;
+        self.decompress_ind = self.decompress_ind.wrapping_add(1);
+        self.decompress_written += 1;
     }
 
     // Handles the 4-byte buffer and writes data to the output file.
@@ -90,44 +310,47 @@ impl Decompress {
     }
 
     // This command will load a series of bytes following the command byte.
-    fn cmd_run(&mut self) {
+    fn cmd_run(&mut self) -> Result<(), DecompressErrorKind> {
         // Get the length of the run command from the command byte.
-        self.cmd &= 0x007F;
-        self.cmd -= 1;
+        let len = self.cmd & 0x007F;
 
-        // Loop until we reach the end of the command.
-        loop {
+        for _ in 0..len {
             // Grab a byte of data from the compressed data
-            let data = self.read_init();
+            let data = self.read_init().ok_or(DecompressErrorKind::TruncatedRun)?;
 
             // Write it out
             self.write_out(data);
 
             // Write the grabbed byte to the decompression buffer
             self.write_buf(data);
-
-            // Decrease the length, and check if the command is done.
-            self.cmd -= 1;
-            if self.cmd == 0xFFFFFFFF {
-                break;
-            }
         }
+
+        Ok(())
     }
 
     // This command will load data from the decompression buffer, rather than the compressed file.
-    fn cmd_cache(&mut self) {
-        // Grab the number of bytes to load from the cache.
-        self.cmd &= 0x007F;
-        self.cmd += 2;
+    fn cmd_cache(&mut self) -> Result<(), DecompressErrorKind> {
+        // Grab the number of bytes to load from the cache.  Cache commands are at least 3 bytes.
+        let len = (self.cmd & 0x007F) + 3;
 
         // The second byte of the command determines where the data will begin being loaded from the buffer.
-        // This requires a bit of calculation..
-        let mut decompress_calcind = self.decompress_ind;
-        decompress_calcind -= self.read_init();
-        decompress_calcind -= 1;
+        // This requires a bit of calculation..  The buffer is 0x100 bytes, so the index wraps.
+        let distance = self
+            .read_init()
+            .ok_or(DecompressErrorKind::MissingCacheOffset)?;
+        let mut decompress_calcind = self.decompress_ind.wrapping_sub(distance).wrapping_sub(1);
 
         // Load data from the buffer until the length of the command runs out
-        loop {
+        for _ in 0..len {
+            // In strict mode, make sure this part of the buffer has actually been written.  The
+            // buffer is filled starting from index 0, so this is only a problem before it wraps.
+            if self.strict
+                && self.decompress_written < 0x100
+                && decompress_calcind as usize >= self.decompress_written
+            {
+                return Err(DecompressErrorKind::UninitializedCacheRead);
+            }
+
             // Load from the decompression buffer
             let data = self.decompress_buf[decompress_calcind as usize];
 
@@ -137,35 +360,74 @@ impl Decompress {
             // Update the decompression buffer with the newly loaded data
             self.write_buf(data);
 
-            // Increase the index we load from, decrease the length of the command, and if we're done, break out of the command.
-            decompress_calcind += 1;
-            self.cmd -= 1;
-            if self.cmd == 0xFFFFFFFF {
-                break;
-            }
+            // Move on to the next byte of the buffer.
+            decompress_calcind = decompress_calcind.wrapping_add(1);
         }
+
+        Ok(())
     }
 
-    pub fn decompress(&mut self) {
+    pub fn decompress(&mut self) -> Result<(), DecompressError> {
+        // Length of the last command, if it was a run.
+        let mut last_run = None;
+
         loop {
+            // Remember where this command starts so errors can point at it.
+            let cmd_ind = self.init_ind;
+            let output_len = self.output_len();
+            let error = |kind| DecompressError {
+                kind,
+                offset: cmd_ind,
+                output_len,
+            };
+
             // Get the current command from the compressed data
-            self.cmd = self.read_init() as u32;
+            self.cmd =
+                self.read_init()
+                    .ok_or(error(DecompressErrorKind::MissingEndCommand))? as u32;
 
             // If the command's highest bit is set, then it is a cache command, otherwise, it's a run command.
             // If the command is 0x00, then we've reached the end of the file, so we break out of the loop.
-            if (self.cmd & 0x80) != 0 {
-                self.cmd_cache();
+            let output_start = self.decompress_written;
+            let kind = if (self.cmd & 0x80) != 0 {
+                self.cmd_cache().map_err(error)?;
+                CommandKind::Cache
             } else if (self.cmd & 0xFF) != 0 {
-                self.cmd_run();
-            } else {
-                if self.output_pre_ind != 0 {
-                    println!(
-                        "[WARN] {} byte(s) discarded when decompressed!",
-                        self.output_pre_ind
-                    );
+                if self.strict && last_run.is_some_and(|len| len < 0x7F) {
+                    return Err(error(DecompressErrorKind::SplitRun));
                 }
+                self.cmd_run().map_err(error)?;
+                CommandKind::Run
+            } else {
+                CommandKind::End
+            };
+
+            // Keep a record of the command if we're tracing.
+            if let Some(commands) = &mut self.commands {
+                commands.push(Command {
+                    offset: cmd_ind,
+                    kind,
+                    length: self.decompress_written - output_start,
+                    distance: match kind {
+                        CommandKind::Cache => Some(self.init_data[cmd_ind + 1] as usize + 1),
+                        _ => None,
+                    },
+                    output_start,
+                });
+            }
+
+            if let CommandKind::End = kind {
                 break;
             }
+            if self.output_len() > self.max_output {
+                return Err(error(DecompressErrorKind::TooLarge));
+            }
+            last_run = match kind {
+                CommandKind::Run => Some(self.cmd),
+                _ => None,
+            };
         }
+
+        Ok(())
     }
 }
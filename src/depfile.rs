@@ -0,0 +1,103 @@
+use crate::PMDTError;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+// The files a command read and wrote, in the order it first touched them.  stdin and stdout
+// aren't included.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Touched {
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+}
+
+thread_local! {
+    static TRACKING: RefCell<Option<Touched>> = const { RefCell::new(None) };
+}
+
+fn push_new(files: &mut Vec<PathBuf>, filename: &Path) {
+    if !files.iter().any(|file| file == filename) {
+        files.push(filename.to_path_buf());
+    }
+}
+
+// Escapes a file name for a Makefile rule, which ninja reads the same way.
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' | '\\' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => (),
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Touched {
+    pub fn merge(&mut self, other: Touched) {
+        for input in other.inputs.iter() {
+            push_new(&mut self.inputs, input);
+        }
+        for output in other.outputs.iter() {
+            push_new(&mut self.outputs, output);
+        }
+    }
+
+    // A Makefile rule with the outputs as targets and the inputs as prerequisites.  Files that were
+    // also written, such as a rom fixed in place, aren't listed as prerequisites of themselves.
+    pub fn to_makefile(&self) -> String {
+        let targets: Vec<String> = self.outputs.iter().map(|file| escape(file)).collect();
+        let mut rule = format!("{}:", targets.join(" "));
+        for input in self.inputs.iter() {
+            if !self.outputs.contains(input) {
+                rule.push_str(" \\\n  ");
+                rule.push_str(&escape(input));
+            }
+        }
+        rule.push('\n');
+        rule
+    }
+
+    // Writes the Makefile rule to filename.  The depfile itself isn't recorded as an output.
+    pub fn write(&self, filename: &str) -> Result<(), PMDTError> {
+        crate::write_untracked(filename, self.to_makefile().as_bytes())
+    }
+}
+
+// Runs f, and returns the files it read and wrote on this thread.  If this is inside another call
+// to track, those files are also added to the outer one.
+pub fn track<T>(f: impl FnOnce() -> T) -> (T, Touched) {
+    let outer = TRACKING.with(|tracking| tracking.replace(Some(Touched::default())));
+    let result = f();
+    let touched = TRACKING
+        .with(|tracking| tracking.replace(outer))
+        .unwrap_or_default();
+    record(touched.clone());
+    (result, touched)
+}
+
+// Adds files touched somewhere else, such as on another thread, to the current call to track.
+pub fn record(touched: Touched) {
+    TRACKING.with(|tracking| {
+        if let Some(tracking) = tracking.borrow_mut().as_mut() {
+            tracking.merge(touched);
+        }
+    });
+}
+
+pub(crate) fn read(filename: &str) {
+    TRACKING.with(|tracking| {
+        if let Some(tracking) = tracking.borrow_mut().as_mut() {
+            push_new(&mut tracking.inputs, Path::new(filename));
+        }
+    });
+}
+
+pub(crate) fn wrote(filename: &str) {
+    TRACKING.with(|tracking| {
+        if let Some(tracking) = tracking.borrow_mut().as_mut() {
+            push_new(&mut tracking.outputs, Path::new(filename));
+        }
+    });
+}
@@ -0,0 +1,23 @@
+use super::PMDTError;
+
+pub struct Dump {}
+
+impl Dump {
+    pub fn run(input_filename: &str, offset: usize) -> Result<(), PMDTError> {
+        let input_data = crate::read_file(input_filename)?;
+
+        // Print every command, even if the stream turns out to be broken part way through.
+        let trace = crate::decompress::trace(&input_data, offset);
+        for command in trace.commands.iter() {
+            println!("{command}");
+        }
+
+        match trace.error {
+            Some(source) => Err(PMDTError::Decompress {
+                path: input_filename.into(),
+                source,
+            }),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,36 +1,125 @@
 use super::PMDTError;
-use std::error::Error;
 
-pub struct FixChecksum {}
+// The checksum stored at 0x18E: the sum of every big-endian word after the header.  A last odd
+// byte isn't counted.
+pub fn checksum(rom: &[u8]) -> u16 {
+    rom.get(0x200..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .fold(0, |sum: u16, word| {
+            sum.wrapping_add(u16::from_be_bytes([word[0], word[1]]))
+        })
+}
 
-impl FixChecksum {
-    pub fn run(input_filename: &str, output_filename: &str) -> Result<(), Box<dyn Error>> {
-        let mut rom = std::fs::read(input_filename)?;
-        if rom.len() <= 0x201 {
-            return Err(Box::new(PMDTError::InvalidRomSize));
+// Stores the correct checksum in rom, which must have a header.  Returns the old checksum if it
+// was wrong.
+pub fn fix(rom: &mut [u8]) -> Option<(u16, u16)> {
+    let stored = u16::from_be_bytes([rom[0x18E], rom[0x18F]]);
+    let calculated = checksum(rom);
+    if stored == calculated {
+        return None;
+    }
+    rom[0x18E..0x190].copy_from_slice(&calculated.to_be_bytes());
+    Some((stored, calculated))
+}
+
+// Escapes text for a JSON string.
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
         }
+    }
+    escaped.push('"');
+    escaped
+}
 
-        let word_length = (rom.len() - 0x200) / 2;
+// Writes a JSON report of a checksum check or fix.
+fn write_report(
+    report_filename: &str,
+    rom_filename: &str,
+    stored: u16,
+    calculated: u16,
+    written: bool,
+) -> Result<(), PMDTError> {
+    let report = format!(
+        "{{\"file\":{},\"stored\":{stored},\"calculated\":{calculated},\"correct\":{},\
+         \"written\":{written}}}\n",
+        json_string(rom_filename),
+        stored == calculated
+    );
+    crate::write_file(report_filename, report.as_bytes())
+}
 
-        let checksum = ((rom[0x18E] as u16) << 8) | rom[0x18F] as u16;
-        let mut calc_checksum: u16 = 0;
+fn read_rom(input_filename: &str) -> Result<Vec<u8>, PMDTError> {
+    let rom = crate::read_file(input_filename)?;
+    if rom.len() <= 0x201 {
+        return Err(PMDTError::InvalidRomSize {
+            path: input_filename.into(),
+            size: rom.len(),
+            min_size: 0x202,
+        });
+    }
+    Ok(rom)
+}
 
-        for index in 0..word_length {
-            calc_checksum += ((rom[0x200 + index * 2] as u16) << 8) | rom[0x201 + index * 2] as u16;
-        }
+pub struct FixChecksum {}
 
-        if checksum == calc_checksum {
-            println!("[MSG] Checksum is correct, nothing to do.");
-        } else {
-            println!(
+impl FixChecksum {
+    // Fixes the checksum and writes the rom to output_filename, even if the checksum was already
+    // right, so build systems always see the output they asked for.
+    pub fn run(
+        input_filename: &str,
+        output_filename: &str,
+        report_filename: Option<&str>,
+    ) -> Result<(), PMDTError> {
+        let mut rom = read_rom(input_filename)?;
+        let checksum = u16::from_be_bytes([rom[0x18E], rom[0x18F]]);
+        let calc_checksum = self::checksum(&rom);
+
+        if fix(&mut rom).is_some() {
+            eprintln!(
                 "[MSG] Checksum is {:04X}, changing to {:04X}",
                 checksum, calc_checksum
             );
-            rom[0x18E] = (calc_checksum >> 8) as u8;
-            rom[0x18F] = calc_checksum as u8;
-            std::fs::write(output_filename, rom)?;
+        } else {
+            eprintln!("[MSG] Checksum is correct.");
         }
+        crate::write_file(output_filename, &rom)?;
 
+        match report_filename {
+            Some(report_filename) => write_report(
+                report_filename,
+                input_filename,
+                checksum,
+                calc_checksum,
+                true,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Checks the checksum without writing the rom.  A wrong checksum is an error.
+    pub fn check(input_filename: &str, report_filename: Option<&str>) -> Result<(), PMDTError> {
+        let rom = read_rom(input_filename)?;
+        let stored = u16::from_be_bytes([rom[0x18E], rom[0x18F]]);
+        let calculated = checksum(&rom);
+
+        if let Some(report_filename) = report_filename {
+            write_report(report_filename, input_filename, stored, calculated, false)?;
+        }
+        if stored != calculated {
+            return Err(PMDTError::WrongChecksum {
+                path: input_filename.into(),
+                stored,
+                calculated,
+            });
+        }
+        eprintln!("[MSG] Checksum is correct.");
         Ok(())
     }
 }
@@ -0,0 +1,350 @@
+use super::PMDTError;
+
+// The header of a Megadrive rom, from 0x100 to 0x1FF.  Text fields have their trailing spaces
+// removed, and bytes that aren't printable ASCII are shown as ".".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub system: String,
+    pub copyright: String,
+    pub domestic_name: String,
+    pub overseas_name: String,
+    pub serial: String,
+    pub checksum: u16,
+    pub io_support: String,
+    pub rom_start: u32,
+    pub rom_end: u32,
+    pub ram_start: u32,
+    pub ram_end: u32,
+    pub sram: Option<Sram>,
+    pub modem: String,
+    pub region: String,
+}
+
+// Save RAM on the cartridge, described by "RA" at 0x1B0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sram {
+    // 0xF8 for SRAM on odd addresses, 0xE0 for even ones, 0xF0 for both.
+    pub kind: u8,
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderProblem {
+    // The system type doesn't start with "SEGA", which the TMSS boot code checks for.
+    NotSega { system: String },
+    RomStart { start: u32 },
+    // The rom end address isn't the last byte of the file.
+    RomEnd { end: u32, expected: u32 },
+    // The region field holds something other than J, U, E or a hex digit.
+    InvalidRegion { region: String },
+    Checksum { stored: u16, calculated: u16 },
+}
+
+impl std::fmt::Display for HeaderProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderProblem::NotSega { system } => {
+                write!(f, "System type \"{system}\" doesn't start with \"SEGA\"")
+            }
+            HeaderProblem::RomStart { start } => {
+                write!(f, "Rom starts at 0x{start:06X} instead of 0x000000")
+            }
+            HeaderProblem::RomEnd { end, expected } => write!(
+                f,
+                "Rom ends at 0x{end:06X}, but the file ends at 0x{expected:06X}"
+            ),
+            HeaderProblem::InvalidRegion { region } => write!(
+                f,
+                "Region \"{region}\" should only hold J, U and E, or a single hex digit"
+            ),
+            HeaderProblem::Checksum { stored, calculated } => write!(
+                f,
+                "Checksum is 0x{stored:04X}, but should be 0x{calculated:04X}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    UnknownField(String),
+    // A text value is longer than its field.
+    TooLong { field: &'static str, max: usize },
+    NotAscii { field: &'static str },
+    BadNumber { field: &'static str, value: String },
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::UnknownField(name) => write!(f, "Unknown header field \"{name}\""),
+            HeaderError::TooLong { field, max } => {
+                write!(f, "{field} can be at most {max} character(s) long")
+            }
+            HeaderError::NotAscii { field } => write!(f, "{field} must be printable ASCII"),
+            HeaderError::BadNumber { field, value } => {
+                write!(f, "Expected a hex address for {field}, not \"{value}\"")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+enum FieldKind {
+    Text,
+    Address,
+}
+
+// Header fields that can be set by name, with their offset and length.  version is the last two
+// characters of serial.
+const FIELDS: &[(&str, usize, usize, FieldKind)] = &[
+    ("system", 0x100, 0x10, FieldKind::Text),
+    ("copyright", 0x110, 0x10, FieldKind::Text),
+    ("domestic-name", 0x120, 0x30, FieldKind::Text),
+    ("overseas-name", 0x150, 0x30, FieldKind::Text),
+    ("serial", 0x180, 0x0E, FieldKind::Text),
+    ("version", 0x18C, 0x02, FieldKind::Text),
+    ("io-support", 0x190, 0x10, FieldKind::Text),
+    ("rom-start", 0x1A0, 0x04, FieldKind::Address),
+    ("rom-end", 0x1A4, 0x04, FieldKind::Address),
+    ("ram-start", 0x1A8, 0x04, FieldKind::Address),
+    ("ram-end", 0x1AC, 0x04, FieldKind::Address),
+    ("modem", 0x1BC, 0x0C, FieldKind::Text),
+    ("region", 0x1F0, 0x03, FieldKind::Text),
+];
+
+// Sets a header field by name.  Text is padded with spaces to fill the field.  Addresses are in
+// hex, and can start with "0x" or "$".  rom-end can also be "auto", for the last byte of rom.
+pub fn set_field(rom: &mut [u8], name: &str, value: &str) -> Result<(), HeaderError> {
+    let (field, offset, len, kind) = FIELDS
+        .iter()
+        .find(|(field, _, _, _)| *field == name)
+        .ok_or_else(|| HeaderError::UnknownField(name.to_string()))?;
+    let bytes = match kind {
+        FieldKind::Text => {
+            if !value.bytes().all(|byte| (0x20..=0x7E).contains(&byte)) {
+                return Err(HeaderError::NotAscii { field });
+            }
+            if value.len() > *len {
+                return Err(HeaderError::TooLong { field, max: *len });
+            }
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.resize(*len, b' ');
+            bytes
+        }
+        FieldKind::Address if *field == "rom-end" && value == "auto" => {
+            (rom.len().saturating_sub(1) as u32).to_be_bytes().to_vec()
+        }
+        FieldKind::Address => {
+            let digits = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix('$'))
+                .unwrap_or(value);
+            u32::from_str_radix(digits, 16)
+                .map_err(|_| HeaderError::BadNumber {
+                    field,
+                    value: value.to_string(),
+                })?
+                .to_be_bytes()
+                .to_vec()
+        }
+    };
+    rom[*offset..offset + len].copy_from_slice(&bytes);
+    Ok(())
+}
+
+fn text(rom: &[u8], offset: usize, len: usize) -> String {
+    rom[offset..offset + len]
+        .iter()
+        .map(|&byte| match byte {
+            0x20..=0x7E => byte as char,
+            _ => '.',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn long(rom: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        rom[offset],
+        rom[offset + 1],
+        rom[offset + 2],
+        rom[offset + 3],
+    ])
+}
+
+impl Header {
+    // Reads the header of rom, or returns None if it's too small to have one.
+    pub fn parse(rom: &[u8]) -> Option<Header> {
+        if rom.len() < 0x200 {
+            return None;
+        }
+
+        let sram = match &rom[0x1B0..0x1B2] {
+            b"RA" => Some(Sram {
+                kind: rom[0x1B2],
+                start: long(rom, 0x1B4),
+                end: long(rom, 0x1B8),
+            }),
+            _ => None,
+        };
+
+        Some(Header {
+            system: text(rom, 0x100, 0x10),
+            copyright: text(rom, 0x110, 0x10),
+            domestic_name: text(rom, 0x120, 0x30),
+            overseas_name: text(rom, 0x150, 0x30),
+            serial: text(rom, 0x180, 0x0E),
+            checksum: u16::from_be_bytes([rom[0x18E], rom[0x18F]]),
+            io_support: text(rom, 0x190, 0x10),
+            rom_start: long(rom, 0x1A0),
+            rom_end: long(rom, 0x1A4),
+            ram_start: long(rom, 0x1A8),
+            ram_end: long(rom, 0x1AC),
+            sram,
+            modem: text(rom, 0x1BC, 0x0C),
+            // Only the first 3 characters are used.  Some roms leave other data after them.
+            region: text(rom, 0x1F0, 0x03),
+        })
+    }
+
+    // Checks the header against rom, the whole file it came from.
+    pub fn problems(&self, rom: &[u8]) -> Vec<HeaderProblem> {
+        let mut problems = Vec::new();
+        if !self.system.trim_start().starts_with("SEGA") {
+            problems.push(HeaderProblem::NotSega {
+                system: self.system.clone(),
+            });
+        }
+        if self.rom_start != 0 {
+            problems.push(HeaderProblem::RomStart {
+                start: self.rom_start,
+            });
+        }
+        let expected = rom.len().saturating_sub(1) as u32;
+        if self.rom_end != expected {
+            problems.push(HeaderProblem::RomEnd {
+                end: self.rom_end,
+                expected,
+            });
+        }
+        let region = self.region.trim();
+        let old_style = region.chars().all(|c| matches!(c, 'J' | 'U' | 'E'));
+        let new_style = region.len() == 1 && region.chars().all(|c| c.is_ascii_hexdigit());
+        if region.is_empty() || !(old_style || new_style) {
+            problems.push(HeaderProblem::InvalidRegion {
+                region: self.region.clone(),
+            });
+        }
+        let calculated = crate::fix_checksum::checksum(rom);
+        if self.checksum != calculated {
+            problems.push(HeaderProblem::Checksum {
+                stored: self.checksum,
+                calculated,
+            });
+        }
+        problems
+    }
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "System type:   {}", self.system)?;
+        writeln!(f, "Copyright:     {}", self.copyright)?;
+        writeln!(f, "Domestic name: {}", self.domestic_name)?;
+        writeln!(f, "Overseas name: {}", self.overseas_name)?;
+        writeln!(f, "Serial:        {}", self.serial)?;
+        writeln!(f, "Checksum:      0x{:04X}", self.checksum)?;
+        writeln!(f, "I/O support:   {}", self.io_support)?;
+        writeln!(
+            f,
+            "ROM:           0x{:06X}-0x{:06X}",
+            self.rom_start, self.rom_end
+        )?;
+        writeln!(
+            f,
+            "RAM:           0x{:06X}-0x{:06X}",
+            self.ram_start, self.ram_end
+        )?;
+        match self.sram {
+            Some(sram) => writeln!(
+                f,
+                "SRAM:          0x{:06X}-0x{:06X} (type 0x{:02X})",
+                sram.start, sram.end, sram.kind
+            )?,
+            None => writeln!(f, "SRAM:          none")?,
+        }
+        writeln!(f, "Modem:         {}", self.modem)?;
+        write!(f, "Region:        {}", self.region)
+    }
+}
+
+pub struct ShowHeader {}
+
+impl ShowHeader {
+    pub fn run(input_filename: &str) -> Result<(), PMDTError> {
+        let rom = crate::read_file(input_filename)?;
+        let header = Header::parse(&rom).ok_or_else(|| PMDTError::InvalidRomSize {
+            path: input_filename.into(),
+            size: rom.len(),
+            min_size: 0x200,
+        })?;
+
+        println!("{header}");
+        for problem in header.problems(&rom) {
+            eprintln!("[WARN] {problem}");
+        }
+
+        Ok(())
+    }
+}
+
+pub struct EditHeader {}
+
+impl EditHeader {
+    // Sets each name=value pair in fields, in order, then fixes the checksum.
+    pub fn run(
+        input_filename: &str,
+        output_filename: &str,
+        check_newer: bool,
+        fields: &[&str],
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(input_filename, output_filename, check_newer)? {
+            return Ok(());
+        }
+
+        let mut rom = crate::read_file(input_filename)?;
+        if rom.len() < 0x200 {
+            return Err(PMDTError::InvalidRomSize {
+                path: input_filename.into(),
+                size: rom.len(),
+                min_size: 0x200,
+            });
+        }
+
+        for field in fields {
+            let (name, value) =
+                field
+                    .split_once('=')
+                    .ok_or_else(|| PMDTError::InvalidArgument {
+                        argument: field.to_string(),
+                        reason: "Expected name=value".into(),
+                    })?;
+            set_field(&mut rom, name, value).map_err(|source| PMDTError::Header {
+                argument: field.to_string(),
+                source,
+            })?;
+        }
+
+        if let Some((checksum, calc_checksum)) = crate::fix_checksum::fix(&mut rom) {
+            eprintln!(
+                "[MSG] Checksum is {:04X}, changing to {:04X}",
+                checksum, calc_checksum
+            );
+        }
+        crate::write_file(output_filename, &rom)
+    }
+}
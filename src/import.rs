@@ -0,0 +1,202 @@
+use super::PMDTError;
+use super::palette::{ColorMapping, load_colors};
+use super::png::{Image, ImageData, PngError};
+
+// Reasons an image can't be turned into tiles.  Positions are in pixels, or in tiles for the
+// variants that name a tile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    // The image couldn't be decoded.
+    Png(PngError),
+    // The image isn't a whole number of tiles.
+    BadSize {
+        width: usize,
+        height: usize,
+    },
+    // An indexed image uses a palette index that is too large.
+    IndexTooLarge {
+        x: usize,
+        y: usize,
+        index: u8,
+        limit: usize,
+    },
+    // A truecolor image was given without a palette.
+    NeedsPalette,
+    // A truecolor pixel doesn't match any color in the palette.
+    ColorNotInPalette {
+        x: usize,
+        y: usize,
+        color: [u8; 3],
+    },
+    // A tile uses colors from more than one palette line.
+    MixedPaletteLines {
+        tile_x: usize,
+        tile_y: usize,
+    },
+    // A tile doesn't fit in any one palette line.
+    NoPaletteLine {
+        tile_x: usize,
+        tile_y: usize,
+    },
+    // The unique tiles don't fit in the 11-bit tile index of a nametable word.
+    TooManyTiles {
+        count: usize,
+        tile_base: usize,
+    },
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Png(error) => write!(f, "{error}"),
+            ImportError::BadSize { width, height } => write!(
+                f,
+                "Image is {width}x{height}, but the width and height must be multiples of 8"
+            ),
+            ImportError::IndexTooLarge { x, y, index, limit } => write!(
+                f,
+                "Pixel ({x}, {y}) uses palette index {index}, but only {limit} colors are allowed"
+            ),
+            ImportError::NeedsPalette => {
+                write!(f, "A palette is needed to import a truecolor image")
+            }
+            ImportError::ColorNotInPalette {
+                x,
+                y,
+                color: [r, g, b],
+            } => write!(
+                f,
+                "Pixel ({x}, {y}) has color #{r:02X}{g:02X}{b:02X}, which isn't in the palette"
+            ),
+            ImportError::MixedPaletteLines { tile_x, tile_y } => write!(
+                f,
+                "Tile at ({tile_x}, {tile_y}) uses colors from more than one palette line"
+            ),
+            ImportError::NoPaletteLine { tile_x, tile_y } => write!(
+                f,
+                "Tile at ({tile_x}, {tile_y}) doesn't fit in any one palette line"
+            ),
+            ImportError::TooManyTiles { count, tile_base } => write!(
+                f,
+                "Image needs 0x{count:X} tile(s) starting at 0x{tile_base:X}, which doesn't fit \
+                 in a nametable's tile index"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+// Converts an image to color indexes.  Indexed images use their own palette indexes, which must
+// all be below 0x10.  Any other image has each pixel looked up in colors, except for fully
+// transparent pixels, which become color 0.  Pixels and colors are both rounded to the nearest
+// levels in mapping first, so an image saved with other levels, or slightly off after editing,
+// still matches.
+pub fn image_to_indexes(
+    image: &Image,
+    colors: Option<&[[u8; 3]]>,
+    mapping: ColorMapping,
+) -> Result<Vec<u8>, ImportError> {
+    let position = |i: usize| (i % image.width, i / image.width);
+    match &image.data {
+        ImageData::Indexed { indexes, .. } => {
+            if let Some(i) = indexes.iter().position(|index| *index >= 0x10) {
+                let (x, y) = position(i);
+                return Err(ImportError::IndexTooLarge {
+                    x,
+                    y,
+                    index: indexes[i],
+                    limit: 0x10,
+                });
+            }
+            Ok(indexes.clone())
+        }
+        ImageData::Rgba(pixels) => {
+            let colors: Vec<[u8; 3]> = colors
+                .ok_or(ImportError::NeedsPalette)?
+                .iter()
+                .take(0x10)
+                .map(|color| mapping.snap(*color))
+                .collect();
+            pixels
+                .iter()
+                .enumerate()
+                .map(|(i, [r, g, b, a])| {
+                    if *a == 0 {
+                        return Ok(0);
+                    }
+                    colors
+                        .iter()
+                        .position(|color| *color == mapping.snap([*r, *g, *b]))
+                        .map(|index| index as u8)
+                        .ok_or_else(|| {
+                            let (x, y) = position(i);
+                            ImportError::ColorNotInPalette {
+                                x,
+                                y,
+                                color: [*r, *g, *b],
+                            }
+                        })
+                })
+                .collect()
+        }
+    }
+}
+
+// Cuts a PNG image into 4bpp tiles.  See image_to_indexes for how pixels are matched to colors.
+pub fn import_tiles(
+    png: &[u8],
+    colors: Option<&[[u8; 3]]>,
+    mapping: ColorMapping,
+) -> Result<Vec<u8>, ImportError> {
+    let image = crate::png::decode(png).map_err(ImportError::Png)?;
+    if image.width % 8 != 0 || image.height % 8 != 0 {
+        return Err(ImportError::BadSize {
+            width: image.width,
+            height: image.height,
+        });
+    }
+
+    let indexes = image_to_indexes(&image, colors, mapping)?;
+    Ok(crate::tiles::pixels_to_tiles(
+        image.width,
+        image.height,
+        &indexes,
+    ))
+}
+
+pub struct Import {}
+
+impl Import {
+    pub fn run(
+        input_filename: &str,
+        output_filename: &str,
+        check_newer: bool,
+        compressed: bool,
+        palette_filename: Option<&str>,
+        mapping: ColorMapping,
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(input_filename, output_filename, check_newer)? {
+            return Ok(());
+        }
+
+        let colors = match palette_filename {
+            Some(palette_filename) => Some(load_colors(&[palette_filename], mapping)?),
+            None => None,
+        };
+        let tiles = import_tiles(
+            &crate::read_file(input_filename)?,
+            colors.as_deref(),
+            mapping,
+        )
+        .map_err(|source| PMDTError::Import {
+            path: input_filename.into(),
+            source,
+        })?;
+
+        crate::write_file(
+            output_filename,
+            &crate::compress_tiles(tiles, compressed, input_filename)?,
+        )
+    }
+}
@@ -1,45 +1,328 @@
-use std::error::Error;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
+pub mod build_background;
+pub mod cache;
 pub mod compress;
 pub mod convert;
 pub mod decompress;
+pub mod depfile;
+pub mod dump;
 pub mod fix_checksum;
+pub mod header;
+pub mod import;
+pub mod palette;
+pub mod png;
+pub mod render;
+pub mod render_background;
+pub mod scan;
+pub mod tiles;
 
-// Checks if output_filename is newer than input_filename.
+// A file name of "-" means stdin when reading, and stdout when writing.
+const STDIO: &str = "-";
+
+// Checks if output_filename is newer than input_filename.  stdin and stdout are never up to date.
 fn check_output_newer(
     input_filename: &str,
     output_filename: &str,
     check_newer: bool,
-) -> Result<bool, Box<dyn Error>> {
-    if check_newer && std::fs::exists(output_filename)? {
+) -> Result<bool, PMDTError> {
+    if check_newer
+        && input_filename != STDIO
+        && output_filename != STDIO
+        && std::fs::exists(output_filename).map_err(io_error(output_filename))?
+    {
         // Get Metadata
-        let input_file_meta = std::fs::metadata(input_filename)?;
-        let output_file_meta = std::fs::metadata(output_filename)?;
+        let input_file_meta =
+            std::fs::metadata(input_filename).map_err(io_error(input_filename))?;
+        let output_file_meta =
+            std::fs::metadata(output_filename).map_err(io_error(output_filename))?;
 
         // Check if the output file is newer.
         if input_file_meta.modified().is_ok() {
-            let input_file_time = input_file_meta.modified()?;
-            let output_file_time = output_file_meta.modified()?;
+            let input_file_time = input_file_meta
+                .modified()
+                .map_err(io_error(input_filename))?;
+            let output_file_time = output_file_meta
+                .modified()
+                .map_err(io_error(output_filename))?;
             if output_file_time > input_file_time {
                 return Ok(true);
             }
+        } else {
+            eprintln!(
+                "[WARN] Modification times aren't available here, so the output is always \
+                 rebuilt.  Use --cache instead."
+            );
         }
     }
     Ok(false)
 }
 
+// Wraps an io error with the file it happened on.
+fn io_error(filename: &str) -> impl FnOnce(std::io::Error) -> PMDTError + '_ {
+    move |source| PMDTError::Io {
+        path: filename.into(),
+        source,
+    }
+}
+
+// Reads a whole file, or all of stdin.
+fn read_file(filename: &str) -> Result<Vec<u8>, PMDTError> {
+    if filename == STDIO {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .map_err(io_error(filename))?;
+        return Ok(data);
+    }
+    let data = std::fs::read(filename).map_err(io_error(filename))?;
+    depfile::read(filename);
+    Ok(data)
+}
+
+// Writes a whole file, creating its directory first if it doesn't exist, or writes to stdout.
+fn write_file(filename: &str, data: &[u8]) -> Result<(), PMDTError> {
+    write_untracked(filename, data)?;
+    if filename != STDIO {
+        depfile::wrote(filename);
+    }
+    Ok(())
+}
+
+// Writes a file without listing it in a depfile.
+fn write_untracked(filename: &str, data: &[u8]) -> Result<(), PMDTError> {
+    if filename == STDIO {
+        let mut stdout = std::io::stdout().lock();
+        return stdout
+            .write_all(data)
+            .and_then(|_| stdout.flush())
+            .map_err(io_error(filename));
+    }
+    if let Some(prefix) = Path::new(filename).parent() {
+        std::fs::create_dir_all(prefix).map_err(io_error(filename))?;
+    }
+    std::fs::write(filename, data).map_err(io_error(filename))
+}
+
+// Reads tile data for the graphics commands, decompressing it first if compressed is set.
+fn read_tiles(filename: &str, compressed: bool) -> Result<Vec<u8>, PMDTError> {
+    let data = read_file(filename)?;
+    if !compressed {
+        return Ok(data);
+    }
+    decompress::decompress(&data, false).map_err(|source| PMDTError::Decompress {
+        path: filename.into(),
+        source,
+    })
+}
+
+// Compresses tile data made from input_filename if compressed is set.  Tiles are always a
+// multiple of 4 bytes long, so this can be verified.
+fn compress_tiles(
+    tiles: Vec<u8>,
+    compressed: bool,
+    input_filename: &str,
+) -> Result<Vec<u8>, PMDTError> {
+    if !compressed {
+        return Ok(tiles);
+    }
+    compress::compress_with_options(&tiles, &compress::CompressOptions::default()).map_err(
+        |source| PMDTError::Compress {
+            path: input_filename.into(),
+            source,
+        },
+    )
+}
+
+// Errors that come from a file carry its path, and errors from the codecs and file formats are
+// wrapped whole, so they can be matched on.
 #[derive(Debug)]
 pub enum PMDTError {
-    InvalidNumOfArguments,
-    InvalidRomSize,
-    UnknownMappingType,
-    WrongMappingSize,
+    // The first argument isn't a known command.
+    UnknownCommand {
+        command: String,
+    },
+    // A command was given the wrong number of arguments.
+    InvalidNumOfArguments {
+        command: String,
+    },
+    // A command line argument couldn't be used.
+    InvalidArgument {
+        argument: String,
+        reason: String,
+    },
+    // Reading, writing or checking the modification time of a file failed.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    // A rom is too small to checksum.
+    InvalidRomSize {
+        path: PathBuf,
+        size: usize,
+        min_size: usize,
+    },
+    // fix --check found a checksum that doesn't match the rom.
+    WrongChecksum {
+        path: PathBuf,
+        stored: u16,
+        calculated: u16,
+    },
+    // A mapping file name doesn't end in bgword, bgpalm or bgbyte.
+    UnknownMappingType {
+        path: PathBuf,
+    },
+    // A mapping file's length doesn't fit its type, or doesn't match its bgpalp file.
+    WrongMappingSize {
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    },
+    // A palette file name doesn't end in a known palette extension.
+    UnknownPaletteFormat {
+        path: PathBuf,
+    },
+    Compress {
+        path: PathBuf,
+        source: compress::CompressError,
+    },
+    Decompress {
+        path: PathBuf,
+        source: decompress::DecompressError,
+    },
+    Palette {
+        path: PathBuf,
+        source: palette::PaletteError,
+    },
+    Import {
+        path: PathBuf,
+        source: import::ImportError,
+    },
+    // A setheader field couldn't be set.  argument is the whole name=value pair.
+    Header {
+        argument: String,
+        source: header::HeaderError,
+    },
+    // A batch manifest couldn't be read.  Lines are counted from 1.
+    InvalidManifest {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    // A command listed in a batch manifest failed.
+    BatchEntry {
+        path: PathBuf,
+        line: usize,
+        source: Box<PMDTError>,
+    },
 }
 
 impl std::fmt::Display for PMDTError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            PMDTError::UnknownCommand { command } => write!(f, "Unknown command \"{command}\""),
+            PMDTError::InvalidNumOfArguments { command } => {
+                write!(f, "Wrong number of arguments for \"{command}\"")
+            }
+            PMDTError::InvalidArgument { argument, reason } => {
+                write!(f, "Invalid argument \"{argument}\": {reason}")
+            }
+            PMDTError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            PMDTError::InvalidRomSize {
+                path,
+                size,
+                min_size,
+            } => write!(
+                f,
+                "{}: Rom is 0x{size:X} byte(s), but must be at least 0x{min_size:X}",
+                path.display()
+            ),
+            PMDTError::WrongChecksum {
+                path,
+                stored,
+                calculated,
+            } => write!(
+                f,
+                "{}: Checksum is 0x{stored:04X}, but should be 0x{calculated:04X}",
+                path.display()
+            ),
+            PMDTError::UnknownMappingType { path } => write!(
+                f,
+                "{}: Unknown mapping type (expected a bgword, bgpalm or bgbyte file)",
+                path.display()
+            ),
+            PMDTError::WrongMappingSize {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: Mapping is 0x{actual:X} byte(s), but 0x{expected:X} were expected",
+                path.display()
+            ),
+            PMDTError::UnknownPaletteFormat { path } => write!(
+                f,
+                "{}: Unknown palette format (expected a bin, cram, gpl, pal, act, hex or txt file)",
+                path.display()
+            ),
+            PMDTError::Compress { path, source } => write!(f, "{}: {source}", path.display()),
+            PMDTError::Decompress { path, source } => write!(f, "{}: {source}", path.display()),
+            PMDTError::Palette { path, source } => write!(f, "{}: {source}", path.display()),
+            PMDTError::Import { path, source } => write!(f, "{}: {source}", path.display()),
+            PMDTError::Header { argument, source } => {
+                write!(f, "Invalid argument \"{argument}\": {source}")
+            }
+            PMDTError::InvalidManifest { path, line, reason } => {
+                write!(f, "{} line {line}: {reason}", path.display())
+            }
+            PMDTError::BatchEntry { path, line, source } => {
+                write!(f, "{} line {line}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl PMDTError {
+    // The exit code the command line tool uses for this error, so build systems can tell what
+    // kind of problem stopped them.
+    //  - 2: the command line (or a batch manifest) was wrong.
+    //  - 3: a file couldn't be read or written.
+    //  - 4: an input file isn't valid for the command, or fails fix --check.
+    //  - 5: data couldn't be compressed so that it decompresses back to itself.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PMDTError::UnknownCommand { .. }
+            | PMDTError::InvalidNumOfArguments { .. }
+            | PMDTError::InvalidArgument { .. }
+            | PMDTError::Header { .. }
+            | PMDTError::InvalidManifest { .. } => 2,
+            PMDTError::Io { .. } => 3,
+            PMDTError::InvalidRomSize { .. }
+            | PMDTError::WrongChecksum { .. }
+            | PMDTError::UnknownMappingType { .. }
+            | PMDTError::WrongMappingSize { .. }
+            | PMDTError::UnknownPaletteFormat { .. }
+            | PMDTError::Decompress { .. }
+            | PMDTError::Palette { .. }
+            | PMDTError::Import { .. } => 4,
+            PMDTError::Compress { .. } => 5,
+            PMDTError::BatchEntry { source, .. } => source.exit_code(),
+        }
     }
 }
 
-impl std::error::Error for PMDTError {}
+impl std::error::Error for PMDTError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PMDTError::Io { source, .. } => Some(source),
+            PMDTError::Compress { source, .. } => Some(source),
+            PMDTError::Decompress { source, .. } => Some(source),
+            PMDTError::Palette { source, .. } => Some(source),
+            PMDTError::Import { source, .. } => Some(source),
+            PMDTError::Header { source, .. } => Some(source),
+            PMDTError::BatchEntry { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
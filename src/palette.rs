@@ -0,0 +1,406 @@
+// Megadrive palettes.  Colors are stored in CRAM as big endian words in the form 0000BBB0GGG0RRR0,
+// with 16 colors to a palette line.
+
+use super::PMDTError;
+
+// Ways of turning the 8 levels of a 3-bit color channel into 8-bit values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMapping {
+    // Scaled evenly, so that 7 becomes 0xFF.
+    Linear,
+    // The level shifted into the top bits of the byte, so that 7 becomes 0xE0.
+    Shift,
+    // The levels measured from the video output of real hardware, which aren't evenly spaced.
+    Hardware,
+}
+
+impl ColorMapping {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(ColorMapping::Linear),
+            "shift" => Some(ColorMapping::Shift),
+            "hardware" => Some(ColorMapping::Hardware),
+            _ => None,
+        }
+    }
+
+    pub fn levels(&self) -> [u8; 8] {
+        match self {
+            ColorMapping::Linear => [0x00, 0x24, 0x48, 0x6D, 0x91, 0xB6, 0xDA, 0xFF],
+            ColorMapping::Shift => [0x00, 0x20, 0x40, 0x60, 0x80, 0xA0, 0xC0, 0xE0],
+            ColorMapping::Hardware => [0x00, 0x34, 0x57, 0x74, 0x90, 0xAC, 0xCE, 0xFF],
+        }
+    }
+
+    // Rounds each channel of an 8-bit RGB color to the nearest level, so colors can be compared
+    // without being exactly equal.
+    pub fn snap(&self, color: [u8; 3]) -> [u8; 3] {
+        let levels = self.levels();
+        color.map(|value| levels[self.nearest_level(value) as usize])
+    }
+
+    // Finds the 3-bit level closest to an 8-bit value.
+    fn nearest_level(&self, value: u8) -> u16 {
+        self.levels()
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, level)| (**level as i16 - value as i16).abs())
+            .map(|(level, _)| level as u16)
+            .unwrap_or(0)
+    }
+}
+
+// Palette file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    // Raw CRAM words.
+    Cram,
+    // GIMP palette.
+    Gpl,
+    // Paint Shop Pro palette.
+    Jasc,
+    // Adobe color table.
+    Act,
+    // CRAM words written out in hex.
+    Hex,
+}
+
+impl PaletteFormat {
+    // Infers the format from a file extension, the same way mapping types are.
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        let extension = std::path::Path::new(filename)
+            .extension()?
+            .to_str()?
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "bin" | "cram" => Some(PaletteFormat::Cram),
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::Jasc),
+            "act" => Some(PaletteFormat::Act),
+            "hex" | "txt" => Some(PaletteFormat::Hex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    // The file doesn't start with the header its format needs.
+    MissingHeader(&'static str),
+    // A line couldn't be read.  Lines are counted from 1.
+    BadLine(usize),
+    // The file is the wrong length for its format.
+    BadLength(usize),
+    // The palette has more colors than the format can hold.
+    TooManyColors(usize),
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::MissingHeader(header) => {
+                write!(f, "Palette file doesn't start with \"{header}\"")
+            }
+            PaletteError::BadLine(line) => write!(f, "Couldn't read line {line} of palette file"),
+            PaletteError::BadLength(len) => {
+                write!(
+                    f,
+                    "Palette file is 0x{len:X} byte(s), which doesn't fit its format"
+                )
+            }
+            PaletteError::TooManyColors(count) => {
+                write!(
+                    f,
+                    "Palette has {count} colors, which is too many for its format"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+pub struct Palette {
+    colors: Vec<u16>,
+}
+
+impl Palette {
+    // Reads raw CRAM words.  A trailing odd byte is ignored.
+    pub fn from_cram(data: &[u8]) -> Self {
+        Self {
+            colors: data
+                .chunks_exact(2)
+                .map(|word| (word[0] as u16) << 8 | word[1] as u16)
+                .collect(),
+        }
+    }
+
+    pub fn to_cram(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|color| color.to_be_bytes())
+            .collect()
+    }
+
+    // Rounds 8-bit RGB colors to the nearest levels in mapping.
+    pub fn from_rgb(colors: &[[u8; 3]], mapping: ColorMapping) -> Self {
+        Self {
+            colors: colors
+                .iter()
+                .map(|[r, g, b]| {
+                    mapping.nearest_level(*r) << 1
+                        | mapping.nearest_level(*g) << 5
+                        | mapping.nearest_level(*b) << 9
+                })
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    // Returns the colors as 8-bit RGB, using ColorMapping::Linear.
+    pub fn to_rgb(&self) -> Vec<[u8; 3]> {
+        self.to_rgb_with(ColorMapping::Linear)
+    }
+
+    pub fn to_rgb_with(&self, mapping: ColorMapping) -> Vec<[u8; 3]> {
+        let levels = mapping.levels();
+        let scale = |level: u16| levels[(level & 0x7) as usize];
+        self.colors
+            .iter()
+            .map(|color| [scale(color >> 1), scale(color >> 5), scale(color >> 9)])
+            .collect()
+    }
+
+    // Reads a palette file.  mapping is used to round the colors of RGB formats.
+    pub fn read(
+        data: &[u8],
+        format: PaletteFormat,
+        mapping: ColorMapping,
+    ) -> Result<Self, PaletteError> {
+        match format {
+            PaletteFormat::Cram => Ok(Self::from_cram(data)),
+            PaletteFormat::Gpl => Ok(Self::from_rgb(&read_gpl(data)?, mapping)),
+            PaletteFormat::Jasc => Ok(Self::from_rgb(&read_jasc(data)?, mapping)),
+            PaletteFormat::Act => Ok(Self::from_rgb(&read_act(data)?, mapping)),
+            PaletteFormat::Hex => Ok(Self {
+                colors: read_hex(data)?,
+            }),
+        }
+    }
+
+    // Writes a palette file.  mapping is used to pick the colors of RGB formats.
+    pub fn write(
+        &self,
+        format: PaletteFormat,
+        mapping: ColorMapping,
+    ) -> Result<Vec<u8>, PaletteError> {
+        let colors = self.to_rgb_with(mapping);
+        match format {
+            PaletteFormat::Cram => Ok(self.to_cram()),
+            PaletteFormat::Gpl => {
+                let mut output = String::from("GIMP Palette\nName: Megadrive\nColumns: 16\n#\n");
+                for (i, [r, g, b]) in colors.iter().enumerate() {
+                    output.push_str(&format!("{r:3} {g:3} {b:3}\tIndex {i}\n"));
+                }
+                Ok(output.into_bytes())
+            }
+            PaletteFormat::Jasc => {
+                let mut output = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+                for [r, g, b] in colors.iter() {
+                    output.push_str(&format!("{r} {g} {b}\r\n"));
+                }
+                Ok(output.into_bytes())
+            }
+            PaletteFormat::Act => {
+                // 256 colors, followed by the number of colors used and the transparent index
+                // (0xFFFF for none).
+                if colors.len() > 0x100 {
+                    return Err(PaletteError::TooManyColors(colors.len()));
+                }
+                let mut output: Vec<u8> = colors.iter().flatten().copied().collect();
+                output.resize(0x300, 0);
+                output.extend((colors.len() as u16).to_be_bytes());
+                output.extend([0xFF, 0xFF]);
+                Ok(output)
+            }
+            PaletteFormat::Hex => {
+                // One palette line per line of text.
+                let mut output = String::new();
+                for line in self.colors.chunks(0x10) {
+                    let words: Vec<String> =
+                        line.iter().map(|word| format!("{word:04X}")).collect();
+                    output.push_str(&words.join(" "));
+                    output.push('\n');
+                }
+                Ok(output.into_bytes())
+            }
+        }
+    }
+}
+
+// Reads "r g b" from the start of a line of text.
+fn parse_rgb(line: &str, line_num: usize) -> Result<[u8; 3], PaletteError> {
+    let mut values = line.split_whitespace().map(|value| value.parse::<u8>());
+    let mut next = || match values.next() {
+        Some(Ok(value)) => Ok(value),
+        _ => Err(PaletteError::BadLine(line_num)),
+    };
+    Ok([next()?, next()?, next()?])
+}
+
+// GIMP palettes have a "GIMP Palette" line, optional Name and Columns lines, "#" comments, and
+// then one color per line with an optional name after it.
+fn read_gpl(data: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("GIMP Palette") {
+        return Err(PaletteError::MissingHeader("GIMP Palette"));
+    }
+
+    let mut colors = Vec::new();
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        colors.push(parse_rgb(line, i + 1)?);
+    }
+    Ok(colors)
+}
+
+// JASC palettes have a "JASC-PAL" line, a version line, the number of colors, and then one color
+// per line.
+fn read_jasc(data: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let text = String::from_utf8_lossy(data);
+    let lines: Vec<&str> = text.lines().map(|line| line.trim()).collect();
+    if lines.first() != Some(&"JASC-PAL") {
+        return Err(PaletteError::MissingHeader("JASC-PAL"));
+    }
+    let count: usize = lines
+        .get(2)
+        .and_then(|line| line.parse().ok())
+        .ok_or(PaletteError::BadLine(3))?;
+
+    (3..3 + count)
+        .map(|i| parse_rgb(lines.get(i).unwrap_or(&""), i + 1))
+        .collect()
+}
+
+// Adobe color tables are 256 RGB colors, optionally followed by a big endian color count and
+// transparent index.
+fn read_act(data: &[u8]) -> Result<Vec<[u8; 3]>, PaletteError> {
+    let count = match data.len() {
+        0x300 => 0x100,
+        0x304 => match (data[0x300] as usize) << 8 | data[0x301] as usize {
+            0 => 0x100,
+            count => count.min(0x100),
+        },
+        len => return Err(PaletteError::BadLength(len)),
+    };
+    Ok(data[..count * 3]
+        .chunks_exact(3)
+        .map(|color| [color[0], color[1], color[2]])
+        .collect())
+}
+
+// Hex palettes are CRAM words separated by spaces, commas or new lines.  Words may start with "$"
+// or "0x", and "dc.w" and comments starting with ";" are skipped, so palettes can be copied
+// straight out of a disassembly.
+fn read_hex(data: &[u8]) -> Result<Vec<u16>, PaletteError> {
+    let text = String::from_utf8_lossy(data);
+    let mut colors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("");
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            let word = word.trim_start_matches('$').trim_start_matches("0x");
+            if word.is_empty() || word.eq_ignore_ascii_case("dc.w") {
+                continue;
+            }
+            colors.push(u16::from_str_radix(word, 16).map_err(|_| PaletteError::BadLine(i + 1))?);
+        }
+    }
+    Ok(colors)
+}
+
+// Reads palette files for the graphics commands and joins their colors together as 8-bit RGB.
+// Each file is read in the format its extension names, and files with any other extension are
+// read as raw CRAM words, which used to be the only format they could be in.
+pub fn load_colors(filenames: &[&str], mapping: ColorMapping) -> Result<Vec<[u8; 3]>, PMDTError> {
+    let mut colors = Vec::new();
+    for filename in filenames {
+        let format = PaletteFormat::from_filename(filename).unwrap_or(PaletteFormat::Cram);
+        let palette =
+            Palette::read(&crate::read_file(filename)?, format, mapping).map_err(|source| {
+                PMDTError::Palette {
+                    path: filename.into(),
+                    source,
+                }
+            })?;
+        colors.extend(palette.to_rgb_with(mapping));
+    }
+    Ok(colors)
+}
+
+// Reads the palette files for a background, which can use at most 4 palette lines.  Files are
+// joined together, so either one file can hold all four lines, or each file can hold one line.
+pub fn load_background_colors(
+    filenames: &[&str],
+    mapping: ColorMapping,
+) -> Result<Vec<[u8; 3]>, PMDTError> {
+    if let Some(extra) = filenames.get(4) {
+        return Err(PMDTError::InvalidArgument {
+            argument: extra.to_string(),
+            reason: "A background can use at most 4 palettes".into(),
+        });
+    }
+    load_colors(filenames, mapping)
+}
+
+pub struct ConvertPalette {}
+
+impl ConvertPalette {
+    pub fn run(
+        input_filename: &str,
+        output_filename: &str,
+        check_newer: bool,
+        mapping: ColorMapping,
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(input_filename, output_filename, check_newer)? {
+            return Ok(());
+        }
+
+        let format_of = |filename: &str| {
+            PaletteFormat::from_filename(filename).ok_or_else(|| PMDTError::UnknownPaletteFormat {
+                path: filename.into(),
+            })
+        };
+        let input_format = format_of(input_filename)?;
+        let output_format = format_of(output_filename)?;
+
+        let palette = Palette::read(&crate::read_file(input_filename)?, input_format, mapping)
+            .map_err(|source| PMDTError::Palette {
+                path: input_filename.into(),
+                source,
+            })?;
+        let output =
+            palette
+                .write(output_format, mapping)
+                .map_err(|source| PMDTError::Palette {
+                    path: output_filename.into(),
+                    source,
+                })?;
+
+        crate::write_file(output_filename, &output)
+    }
+}
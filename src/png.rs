@@ -0,0 +1,521 @@
+// Just enough of a PNG encoder to write out indexed color images.  Image data is stored in
+// uncompressed deflate blocks, which keeps things simple at the cost of larger files.
+
+// Table used to calculate the CRC of each chunk.
+fn crc_table() -> [u32; 0x100] {
+    let mut table = [0; 0x100];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    table
+}
+
+fn crc(table: &[u32; 0x100], data: &[u8]) -> u32 {
+    let mut c = 0xFFFFFFFF;
+    for byte in data {
+        c = table[((c ^ *byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    c ^ 0xFFFFFFFF
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// Wraps data up in a zlib stream made of stored deflate blocks.
+fn zlib_store(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        // An empty stream still needs one final block.
+        output.extend([0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        output.push(if blocks.peek().is_none() { 0x01 } else { 0x00 });
+        output.extend((block.len() as u16).to_le_bytes());
+        output.extend((!(block.len() as u16)).to_le_bytes());
+        output.extend(block);
+    }
+    output.extend(adler32(data).to_be_bytes());
+    output
+}
+
+fn write_chunk(output: &mut Vec<u8>, table: &[u32; 0x100], chunk_type: &[u8; 4], data: &[u8]) {
+    output.extend((data.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend(chunk_type);
+    output.extend(data);
+    let chunk_crc = crc(table, &output[start..]);
+    output.extend(chunk_crc.to_be_bytes());
+}
+
+// Encodes an 8-bit indexed color image.  pixels holds one palette index per pixel, row by row,
+// and palette holds up to 0x100 RGB colors.
+pub fn encode_indexed(width: usize, height: usize, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    let table = crc_table();
+    let mut output = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    // Width, height, bit depth 8, color type 3 (indexed), default compression, filter and no
+    // interlacing.
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 3, 0, 0, 0]);
+    write_chunk(&mut output, &table, b"IHDR", &header);
+
+    write_chunk(&mut output, &table, b"PLTE", palette.as_flattened());
+
+    // Each row starts with a filter type byte, which is always 0 (no filter) here.
+    let mut image_data = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)).take(height) {
+        image_data.push(0);
+        image_data.extend(row);
+    }
+    write_chunk(&mut output, &table, b"IDAT", &zlib_store(&image_data));
+
+    write_chunk(&mut output, &table, b"IEND", &[]);
+    output
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PngError {
+    // The data doesn't start with the PNG signature.
+    NotPng,
+    // The image uses a feature this decoder doesn't handle.
+    Unsupported(&'static str),
+    // The data is damaged or incomplete.
+    Corrupt(&'static str),
+}
+
+impl std::fmt::Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PngError::NotPng => write!(f, "File is not a PNG image"),
+            PngError::Unsupported(what) => write!(f, "Unsupported PNG image: {what}"),
+            PngError::Corrupt(what) => write!(f, "Corrupt PNG image: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+pub enum ImageData {
+    // One palette index per pixel.
+    Indexed {
+        palette: Vec<[u8; 3]>,
+        indexes: Vec<u8>,
+    },
+    // One RGBA color per pixel.  Grayscale and 16-bit images are converted to this.
+    Rgba(Vec<[u8; 4]>),
+}
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: ImageData,
+}
+
+// Reads bits from a deflate stream, starting from the lowest bit of each byte.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, PngError> {
+        let mut val = 0;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(PngError::Corrupt("image data ended early"))?;
+            val |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(val)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// A canonical Huffman code, stored as the number of codes of each length and the symbols in code
+// order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, PngError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or(PngError::Corrupt("bad Huffman code"));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(PngError::Corrupt("bad Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Decodes one block of Huffman compressed data.
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    lit_len: &Huffman,
+    dist: &Huffman,
+) -> Result<(), PngError> {
+    loop {
+        let symbol = lit_len.decode(reader)? as usize;
+        match symbol {
+            0..0x100 => output.push(symbol as u8),
+            0x100 => return Ok(()),
+            _ => {
+                let symbol = symbol - 0x101;
+                if symbol >= 29 {
+                    return Err(PngError::Corrupt("bad length code"));
+                }
+                let len = LENGTH_BASE[symbol] as usize
+                    + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = dist.decode(reader)? as usize;
+                if symbol >= 30 {
+                    return Err(PngError::Corrupt("bad distance code"));
+                }
+                let distance =
+                    DIST_BASE[symbol] as usize + reader.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(PngError::Corrupt("distance too far back"));
+                }
+                for _ in 0..len {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+        }
+    }
+}
+
+// Decompresses a zlib stream.
+fn zlib_inflate(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    if data.len() < 2
+        || data[0] & 0xF != 8
+        || !((data[0] as u16) << 8 | data[1] as u16).is_multiple_of(31)
+    {
+        return Err(PngError::Corrupt("bad zlib header"));
+    }
+
+    let mut reader = BitReader {
+        data: &data[2..],
+        pos: 0,
+        bit: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            // Stored block
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or(PngError::Corrupt("image data ended early"))?;
+                let len = header[0] as usize | (header[1] as usize) << 8;
+                reader.pos += 4;
+                let block = reader
+                    .data
+                    .get(reader.pos..reader.pos + len)
+                    .ok_or(PngError::Corrupt("image data ended early"))?;
+                output.extend(block);
+                reader.pos += len;
+            }
+            // Fixed Huffman codes
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit_len = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &mut output, &lit_len, &dist)?;
+            }
+            // Dynamic Huffman codes
+            2 => {
+                let lit_len_count = reader.bits(5)? as usize + 257;
+                let dist_count = reader.bits(5)? as usize + 1;
+                let code_len_count = reader.bits(4)? as usize + 4;
+
+                const ORDER: [usize; 19] = [
+                    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+                ];
+                let mut code_lengths = [0; 19];
+                for pos in ORDER.iter().take(code_len_count) {
+                    code_lengths[*pos] = reader.bits(3)? as u8;
+                }
+                let code_len = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(lit_len_count + dist_count);
+                while lengths.len() < lit_len_count + dist_count {
+                    let (val, repeat) = match code_len.decode(&mut reader)? {
+                        symbol @ 0..16 => (symbol as u8, 1),
+                        16 => (
+                            *lengths
+                                .last()
+                                .ok_or(PngError::Corrupt("bad code lengths"))?,
+                            3 + reader.bits(2)? as usize,
+                        ),
+                        17 => (0, 3 + reader.bits(3)? as usize),
+                        _ => (0, 11 + reader.bits(7)? as usize),
+                    };
+                    lengths.extend(std::iter::repeat_n(val, repeat));
+                }
+                if lengths.len() != lit_len_count + dist_count {
+                    return Err(PngError::Corrupt("bad code lengths"));
+                }
+
+                let lit_len = Huffman::new(&lengths[..lit_len_count]);
+                let dist = Huffman::new(&lengths[lit_len_count..]);
+                inflate_block(&mut reader, &mut output, &lit_len, &dist)?;
+            }
+            _ => return Err(PngError::Corrupt("bad block type")),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Decodes a PNG image.  Interlaced images aren't supported.
+pub fn decode(data: &[u8]) -> Result<Image, PngError> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(PngError::NotPng);
+    }
+
+    // Collect the chunks we care about.
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut image_data = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let body = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or(PngError::Corrupt("chunk runs past the end of the file"))?;
+        match chunk_type {
+            b"IHDR" if len == 13 => header = Some(body),
+            b"PLTE" => {
+                palette = body
+                    .chunks_exact(3)
+                    .map(|color| [color[0], color[1], color[2]])
+                    .collect()
+            }
+            b"IDAT" => image_data.extend(body),
+            b"IEND" => break,
+            _ => (),
+        }
+        pos += len + 12;
+    }
+
+    let header = header.ok_or(PngError::Corrupt("missing IHDR chunk"))?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let bit_depth = header[8] as usize;
+    let color_type = header[9];
+    if header[12] != 0 {
+        return Err(PngError::Unsupported("interlaced images"));
+    }
+    // Each color type only allows some bit depths.
+    let (channels, bit_depths): (usize, &[usize]) = match color_type {
+        0 => (1, &[1, 2, 4, 8, 16]),
+        2 => (3, &[8, 16]),
+        3 => (1, &[1, 2, 4, 8]),
+        4 => (2, &[8, 16]),
+        6 => (4, &[8, 16]),
+        _ => return Err(PngError::Corrupt("bad color type")),
+    };
+    if !bit_depths.contains(&bit_depth) {
+        return Err(PngError::Corrupt("bad bit depth"));
+    }
+    if width == 0 || height == 0 {
+        return Err(PngError::Corrupt("image has no pixels"));
+    }
+
+    // Undo the filter on each row.  The size comes straight from the file, so check it can't
+    // overflow before trusting it.
+    let raw = zlib_inflate(&image_data)?;
+    let bits_per_pixel = channels * bit_depth;
+    let too_large = PngError::Corrupt("image is too large");
+    let stride = width
+        .checked_mul(bits_per_pixel)
+        .ok_or(too_large.clone())?
+        .div_ceil(8);
+    let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+    let data_len = (stride + 1).checked_mul(height).ok_or(too_large)?;
+    if raw.len() < data_len {
+        return Err(PngError::Corrupt("image data ended early"));
+    }
+    let mut rows = vec![0; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= bytes_per_pixel {
+                rows[y * stride + x - bytes_per_pixel]
+            } else {
+                0
+            };
+            let b = if y > 0 { rows[(y - 1) * stride + x] } else { 0 };
+            let c = if x >= bytes_per_pixel && y > 0 {
+                rows[(y - 1) * stride + x - bytes_per_pixel]
+            } else {
+                0
+            };
+            rows[y * stride + x] = match filter {
+                0 => line[x],
+                1 => line[x].wrapping_add(a),
+                2 => line[x].wrapping_add(b),
+                3 => line[x].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => line[x].wrapping_add(paeth(a, b, c)),
+                _ => return Err(PngError::Corrupt("bad filter type")),
+            };
+        }
+    }
+
+    // Read each sample, scaled down to 8 bits unless it's a palette index.
+    let sample = |y: usize, index: usize| -> u8 {
+        let row = &rows[y * stride..(y + 1) * stride];
+        match bit_depth {
+            16 => row[index * 2],
+            8 => row[index],
+            _ => {
+                let bit = index * bit_depth;
+                let val = (row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1);
+                if color_type == 3 {
+                    val
+                } else {
+                    (val as usize * 0xFF / ((1 << bit_depth) - 1)) as u8
+                }
+            }
+        }
+    };
+
+    let data = if color_type == 3 {
+        ImageData::Indexed {
+            palette,
+            indexes: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (y, x)))
+                .map(|(y, x)| sample(y, x))
+                .collect(),
+        }
+    } else {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let s = |channel| sample(y, x * channels + channel);
+                pixels.push(match color_type {
+                    0 => [s(0), s(0), s(0), 0xFF],
+                    2 => [s(0), s(1), s(2), 0xFF],
+                    4 => [s(0), s(0), s(0), s(1)],
+                    _ => [s(0), s(1), s(2), s(3)],
+                });
+            }
+        }
+        ImageData::Rgba(pixels)
+    };
+
+    Ok(Image {
+        width,
+        height,
+        data,
+    })
+}
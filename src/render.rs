@@ -0,0 +1,48 @@
+use super::PMDTError;
+use super::palette::{ColorMapping, load_colors};
+
+// Renders 4bpp tile data as a PNG tile sheet, tiles_per_row tiles wide.  colors is the RGB palette
+// to use for color indexes 0-15.
+pub fn render_tiles(tiles: &[u8], tiles_per_row: usize, colors: &[[u8; 3]]) -> Vec<u8> {
+    let (width, height, pixels) = crate::tiles::tiles_to_pixels(tiles, tiles_per_row);
+    crate::png::encode_indexed(width, height, colors, &pixels)
+}
+
+pub struct Render {}
+
+impl Render {
+    pub fn run(
+        input_filename: &str,
+        output_filename: &str,
+        check_newer: bool,
+        compressed: bool,
+        tiles_per_row: usize,
+        palette_filename: Option<&str>,
+        mapping: ColorMapping,
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(input_filename, output_filename, check_newer)? {
+            return Ok(());
+        }
+
+        if tiles_per_row == 0 {
+            return Err(PMDTError::InvalidArgument {
+                argument: tiles_per_row.to_string(),
+                reason: "Tile sheet must be at least 1 tile wide".into(),
+            });
+        }
+
+        let tiles = crate::read_tiles(input_filename, compressed)?;
+
+        // Use the first line of the palette, or a grayscale ramp if there isn't one.
+        let mut colors: Vec<[u8; 3]> = match palette_filename {
+            Some(palette_filename) => load_colors(&[palette_filename], mapping)?,
+            None => (0..0x10).map(|i| [i * 0x11; 3]).collect(),
+        };
+        colors.resize(0x10, [0; 3]);
+
+        crate::write_file(
+            output_filename,
+            &render_tiles(&tiles, tiles_per_row, &colors),
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use super::PMDTError;
+use super::convert::Mapping;
+use super::palette::{ColorMapping, load_background_colors};
+use super::tiles::{
+    NAMETABLE_HFLIP, NAMETABLE_VFLIP, nametable_palette, nametable_tile, tile_pixel,
+};
+
+// Renders a background from nametable words, width tiles wide.  Each word's tile index has
+// tile_base subtracted from it to find the tile in tiles, and its palette line and flip bits are
+// applied.  Tiles below tile_base or past the end of tiles are drawn with color 0.  colors holds up
+// to four palette lines of 16 colors each.
+pub fn render_background(
+    words: &[u16],
+    width: usize,
+    tiles: &[u8],
+    tile_base: usize,
+    colors: &[[u8; 3]],
+) -> Vec<u8> {
+    let height = words.len().div_ceil(width);
+    let pixel_width = width * 8;
+
+    let mut pixels = vec![0; pixel_width * height * 8];
+    for (pos, word) in words.iter().enumerate() {
+        let tile = nametable_tile(*word).checked_sub(tile_base);
+        let palette = nametable_palette(*word);
+        let base_x = (pos % width) * 8;
+        let base_y = (pos / width) * 8;
+        for y in 0..8 {
+            for x in 0..8 {
+                let src_x = if word & NAMETABLE_HFLIP != 0 {
+                    7 - x
+                } else {
+                    x
+                };
+                let src_y = if word & NAMETABLE_VFLIP != 0 {
+                    7 - y
+                } else {
+                    y
+                };
+                let color = match tile {
+                    Some(tile) => tile_pixel(tiles, tile, src_x, src_y),
+                    None => 0,
+                };
+                pixels[(base_y + y) * pixel_width + base_x + x] = palette * 0x10 + color;
+            }
+        }
+    }
+
+    crate::png::encode_indexed(pixel_width, height * 8, colors, &pixels)
+}
+
+pub struct RenderBackground {}
+
+impl RenderBackground {
+    #[allow(clippy::too_many_arguments)]
+    pub fn run(
+        mapping_filename: &str,
+        tiles_filename: &str,
+        output_filename: &str,
+        check_newer: bool,
+        compressed: bool,
+        width: usize,
+        common_word: u16,
+        tile_base: usize,
+        palette_filenames: &[&str],
+        color_mapping: ColorMapping,
+    ) -> Result<(), PMDTError> {
+        if crate::check_output_newer(mapping_filename, output_filename, check_newer)? {
+            return Ok(());
+        }
+
+        if width == 0 {
+            return Err(PMDTError::InvalidArgument {
+                argument: width.to_string(),
+                reason: "Background must be at least 1 tile wide".into(),
+            });
+        }
+
+        // Read every word from the mapping.
+        let mut mapping = Mapping::new(mapping_filename, common_word, false, false)?;
+        let mut words = Vec::new();
+        while let Some(word) = mapping.read() {
+            words.push(word);
+        }
+
+        let tiles = crate::read_tiles(tiles_filename, compressed)?;
+
+        // Without any palettes, every line is a grayscale ramp.
+        let mut colors = load_background_colors(palette_filenames, color_mapping)?;
+        if colors.is_empty() {
+            colors = (0..0x40).map(|i| [(i & 0xF) * 0x11; 3]).collect();
+        }
+        colors.resize(0x40, [0; 3]);
+
+        crate::write_file(
+            output_filename,
+            &render_background(&words, width, &tiles, tile_base, &colors),
+        )
+    }
+}
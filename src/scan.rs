@@ -0,0 +1,85 @@
+use super::PMDTError;
+
+// A block of data that looks like it could be compressed art.
+pub struct ScanResult {
+    pub offset: usize,
+    pub compressed_len: usize,
+    pub decompressed_len: usize,
+}
+
+// Filters for scan.  The defaults skip blocks under 4 tiles, which are rarely worth compressing,
+// and blocks that don't shrink to at most 2/3 of their size, since data that isn't compressed
+// often happens to decode to something.  max_tiles defaults to 2048 tiles, which fills VRAM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanOptions {
+    pub min_tiles: usize,
+    pub max_tiles: usize,
+    // Decompressed length divided by compressed length.
+    pub min_ratio: f64,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            min_tiles: 4,
+            max_tiles: 0x800,
+            min_ratio: 1.5,
+        }
+    }
+}
+
+// Tries to decompress at every offset of data, keeping blocks that end cleanly with an end
+// command, pass the strict checks and decompress to a whole number of tiles within the limits in
+// options.  Each try gives up once it has decoded more than max_tiles, so data that isn't
+// compressed can't keep it going for long.
+//
+// Decoding from the middle of a block will often end up in sync with the real commands and finish
+// at the same end command, so only the earliest candidate for each end offset is kept.
+pub fn scan(data: &[u8], options: &ScanOptions) -> Vec<ScanResult> {
+    let mut results = Vec::new();
+    let mut found_ends = std::collections::HashSet::new();
+
+    let max_output = options.max_tiles * 0x20;
+    let mut offset = 0;
+    while offset < data.len() {
+        match crate::decompress::decompress_at_limited(data, offset, false, max_output) {
+            Ok(block)
+                if block.discarded == 0
+                    && !block.data.is_empty()
+                    && block.data.len() % 0x20 == 0
+                    && block.data.len() / 0x20 >= options.min_tiles
+                    && block.data.len() as f64
+                        >= block.compressed_len as f64 * options.min_ratio
+                    && found_ends.insert(offset + block.compressed_len) =>
+            {
+                results.push(ScanResult {
+                    offset,
+                    compressed_len: block.compressed_len,
+                    decompressed_len: block.data.len(),
+                });
+            }
+            _ => (),
+        }
+        offset += 1;
+    }
+
+    results
+}
+
+pub struct Scan {}
+
+impl Scan {
+    pub fn run(input_filename: &str, options: &ScanOptions) -> Result<(), PMDTError> {
+        let rom = crate::read_file(input_filename)?;
+
+        println!("offset   compressed decompressed");
+        for result in scan(&rom, options) {
+            println!(
+                "0x{:06X} 0x{:08X} 0x{:08X}",
+                result.offset, result.compressed_len, result.decompressed_len
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+// Megadrive VDP tiles are 8x8 pixels at 4 bits per pixel, so each tile is 0x20 bytes.  Each byte
+// holds two pixels, with the left one in the high nibble.
+pub const TILE_SIZE: usize = 0x20;
+
+// Returns the color index of pixel (x, y) in the given tile.  Pixels past the end of the data are
+// treated as color 0.
+pub fn tile_pixel(tiles: &[u8], tile: usize, x: usize, y: usize) -> u8 {
+    let byte = tiles
+        .get(tile * TILE_SIZE + y * 4 + x / 2)
+        .copied()
+        .unwrap_or(0);
+    if x & 1 == 0 { byte >> 4 } else { byte & 0xF }
+}
+
+// Flags and fields of a nametable word: priority (bit 15), palette line (bits 13-14), vertical
+// flip (bit 12), horizontal flip (bit 11) and tile index (bits 0-10).
+pub const NAMETABLE_PRIORITY: u16 = 0x8000;
+pub const NAMETABLE_VFLIP: u16 = 0x1000;
+pub const NAMETABLE_HFLIP: u16 = 0x0800;
+
+pub fn nametable_palette(word: u16) -> u8 {
+    ((word >> 13) & 0x3) as u8
+}
+
+pub fn nametable_tile(word: u16) -> usize {
+    (word & 0x07FF) as usize
+}
+
+// Number of tiles in the data, counting a partial tile at the end.
+pub fn tile_count(tiles: &[u8]) -> usize {
+    tiles.len().div_ceil(TILE_SIZE)
+}
+
+// Lays out every tile in rows of tiles_per_row, and returns the width and height in pixels along
+// with one color index per pixel.
+pub fn tiles_to_pixels(tiles: &[u8], tiles_per_row: usize) -> (usize, usize, Vec<u8>) {
+    let rows = tile_count(tiles).div_ceil(tiles_per_row);
+    let width = tiles_per_row * 8;
+    let height = rows * 8;
+
+    let mut pixels = vec![0; width * height];
+    for tile in 0..tile_count(tiles) {
+        let base_x = (tile % tiles_per_row) * 8;
+        let base_y = (tile / tiles_per_row) * 8;
+        for y in 0..8 {
+            for x in 0..8 {
+                pixels[(base_y + y) * width + base_x + x] = tile_pixel(tiles, tile, x, y);
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+// Cuts an image into tiles, going across each row of tiles in turn.  pixels holds one color index
+// (0-15) per pixel, and width and height must both be multiples of 8.
+pub fn pixels_to_tiles(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut tiles = Vec::with_capacity(width * height / 2);
+    for tile_y in (0..height).step_by(8) {
+        for tile_x in (0..width).step_by(8) {
+            for y in tile_y..tile_y + 8 {
+                for x in (tile_x..tile_x + 8).step_by(2) {
+                    tiles.push(pixels[y * width + x] << 4 | pixels[y * width + x + 1]);
+                }
+            }
+        }
+    }
+    tiles
+}
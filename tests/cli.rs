@@ -0,0 +1,477 @@
+mod common;
+
+use common::{path, temp_dir};
+use std::process::{Command, Output};
+
+fn puyomdtool(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_puyomdtool"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn unknown_command_fails() {
+    let output = puyomdtool(&["compres", "a", "b"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown command \"compres\""));
+}
+
+#[test]
+fn exit_codes() {
+    let dir = temp_dir("cli-exit-codes");
+    std::fs::write(path(&dir, "broken.bin"), [0x05, 0x01]).unwrap();
+
+    assert_eq!(puyomdtool(&["compress"]).status.code(), Some(2));
+    assert_eq!(
+        puyomdtool(&["dump", "broken.bin", "zz"]).status.code(),
+        Some(2)
+    );
+    assert_eq!(
+        puyomdtool(&[
+            "decompress",
+            &path(&dir, "missing.bin"),
+            &path(&dir, "out.bin")
+        ])
+        .status
+        .code(),
+        Some(3)
+    );
+    assert_eq!(
+        puyomdtool(&[
+            "decompress",
+            &path(&dir, "broken.bin"),
+            &path(&dir, "out.bin")
+        ])
+        .status
+        .code(),
+        Some(4)
+    );
+}
+
+#[test]
+fn messages_go_to_stderr() {
+    let dir = temp_dir("cli-stderr");
+    std::fs::write(path(&dir, "odd.bin"), [1, 2, 3, 4, 5]).unwrap();
+
+    let output = puyomdtool(&["compress", &path(&dir, "odd.bin"), &path(&dir, "out.bin")]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[WARN]"));
+}
+
+#[test]
+fn old_spellings_still_work() {
+    let dir = temp_dir("cli-old-spellings");
+    std::fs::write(path(&dir, "in.bin"), common::noise(0x200, 7)).unwrap();
+    std::fs::write(path(&dir, "in.bgbyte"), [0x01, 0x02, 0x03]).unwrap();
+
+    let pairs: &[(&[&str], &[&str])] = &[
+        (
+            &["ifnewer", "compress", "in.bin", "old.bin"],
+            &["--if-newer", "compress", "in.bin", "new.bin"],
+        ),
+        (
+            &["convertwordout", "8000", "in.bgbyte", "old.bin"],
+            &[
+                "convert",
+                "--force-word-out",
+                "--common-word",
+                "0x8000",
+                "in.bgbyte",
+                "new.bin",
+            ],
+        ),
+        (
+            &["decompressnobuf", "in.bin", "old.bin", "1"],
+            &["decompress", "--no-buf", "--offset=1", "in.bin", "new.bin"],
+        ),
+    ];
+    for (old, new) in pairs {
+        let old: Vec<String> = old.iter().map(|arg| path_or_arg(&dir, arg)).collect();
+        let new: Vec<String> = new.iter().map(|arg| path_or_arg(&dir, arg)).collect();
+        let old_output = puyomdtool(&old.iter().map(|arg| arg.as_str()).collect::<Vec<_>>());
+        let new_output = puyomdtool(&new.iter().map(|arg| arg.as_str()).collect::<Vec<_>>());
+        assert_eq!(
+            old_output.status.code(),
+            new_output.status.code(),
+            "{old:?}"
+        );
+        assert!(String::from_utf8_lossy(&old_output.stderr).contains("deprecated"));
+        if old_output.status.success() {
+            assert_eq!(
+                std::fs::read(path(&dir, "old.bin")).unwrap(),
+                std::fs::read(path(&dir, "new.bin")).unwrap(),
+                "{old:?}"
+            );
+        }
+    }
+
+    // Only spellings the old tool took are accepted.
+    let output = puyomdtool(&[
+        "compressbest",
+        &path(&dir, "in.bin"),
+        &path(&dir, "old.bin"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+// Turns file names into paths inside dir, and leaves everything else alone.
+fn path_or_arg(dir: &std::path::Path, arg: &str) -> String {
+    if arg.contains('.') {
+        path(dir, arg)
+    } else {
+        arg.to_string()
+    }
+}
+
+#[test]
+fn every_command_has_help() {
+    for command in [
+        "fix",
+        "header",
+        "setheader",
+        "compress",
+        "decompress",
+        "convert",
+        "scan",
+        "dump",
+        "render",
+        "import",
+        "renderbg",
+        "buildbg",
+        "palette",
+        "batch",
+    ] {
+        let output = puyomdtool(&[command, "--help"]);
+        assert!(output.status.success(), "{command}");
+        let help = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            help.starts_with(&format!("Usage: puyomdtool {command} ")),
+            "{command}"
+        );
+        assert_eq!(puyomdtool(&["help", command]).stdout, output.stdout);
+    }
+}
+
+// Runs the tool with input piped into stdin.
+fn puyomdtool_piped(args: &[&str], input: &[u8]) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_puyomdtool"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn dash_means_stdin_and_stdout() {
+    let input = common::noise(0x400, 3);
+    let compressed = puyomdtool::compress::compress(&input);
+
+    let output = puyomdtool_piped(&["compress", "-", "-"], &input);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, compressed);
+
+    let output = puyomdtool_piped(&["decompress", "-"], &compressed);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, input);
+
+    // stdin and stdout mappings are bgword.
+    let dir = temp_dir("cli-stdio");
+    let bgbyte = dir.join("out.bgbyte");
+    let output = puyomdtool_piped(&["convert", "-", bgbyte.to_str().unwrap()], &[0x80, 0x12]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&bgbyte).unwrap(), [0x12]);
+
+    let output = puyomdtool_piped(
+        &[
+            "convert",
+            "--common-word",
+            "0x8000",
+            bgbyte.to_str().unwrap(),
+            "-",
+        ],
+        &[],
+    );
+    assert_eq!(output.stdout, [0x80, 0x12]);
+}
+
+#[test]
+fn batch_runs_a_manifest() {
+    let dir = temp_dir("cli-batch");
+    let input = common::noise(0x400, 5);
+    std::fs::write(path(&dir, "art.bin"), &input).unwrap();
+    std::fs::write(path(&dir, "title.bgword"), [0x80, 0x12, 0x80, 0x34]).unwrap();
+
+    // The decompress is listed first, so it runs before the compress that writes its input.
+    std::fs::write(
+        path(&dir, "build.txt"),
+        format!(
+            "# Art\ndecompress \"{}\" \"{}\"\ncompress --best \"{}\" \"{}\"\n\n\
+             convert \"{}\" \"{}\"  # Mapping\n",
+            path(&dir, "art.cmp"),
+            path(&dir, "text.bin"),
+            path(&dir, "art.bin"),
+            path(&dir, "art.cmp"),
+            path(&dir, "title.bgword"),
+            path(&dir, "title.bgbyte"),
+        ),
+    )
+    .unwrap();
+    let output = puyomdtool(&["batch", &path(&dir, "build.txt")]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("build.txt line 2:"));
+
+    std::fs::write(
+        path(&dir, "build.toml"),
+        format!(
+            "[[compress]]\ninput = '{}'\noutput = '{}'\nbest = true\n\n\
+             [[decompress]]\ninput = '{}'\noutput = '{}'\n\n\
+             [[convert]]\ninput = '{}'\noutput = '{}'\ncommon-word = 0x8000\n",
+            path(&dir, "art.bin"),
+            path(&dir, "toml.cmp"),
+            path(&dir, "toml.cmp"),
+            path(&dir, "toml.bin"),
+            path(&dir, "title.bgbyte"),
+            path(&dir, "again.bgword"),
+        ),
+    )
+    .unwrap();
+    std::fs::write(path(&dir, "title.bgbyte"), [0x12, 0x34]).unwrap();
+    let output = puyomdtool(&["batch", "--jobs", "2", &path(&dir, "build.toml")]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(path(&dir, "toml.bin")).unwrap(), input);
+    assert_eq!(
+        std::fs::read(path(&dir, "again.bgword")).unwrap(),
+        [0x80, 0x12, 0x80, 0x34]
+    );
+
+    // Files written through options, such as depfiles, are waited for too.
+    std::fs::write(
+        path(&dir, "depfile.txt"),
+        format!(
+            "compress --depfile \"{}\" \"{}\" \"{}\"\ncompress \"{}\" \"{}\"\n",
+            path(&dir, "art.d"),
+            path(&dir, "art.bin"),
+            path(&dir, "dep.cmp"),
+            path(&dir, "art.d"),
+            path(&dir, "d.cmp"),
+        ),
+    )
+    .unwrap();
+    let output = puyomdtool(&["batch", &path(&dir, "depfile.txt")]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        std::fs::read(path(&dir, "d.cmp")).unwrap(),
+        puyomdtool::compress::compress(&std::fs::read(path(&dir, "art.d")).unwrap())
+    );
+
+    // Commands run in parallel can't share stdout.
+    std::fs::write(
+        path(&dir, "stdout.txt"),
+        format!("fix --check --json - \"{}\"\n", path(&dir, "art.bin")),
+    )
+    .unwrap();
+    let output = puyomdtool(&["batch", &path(&dir, "stdout.txt")]);
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::write(path(&dir, "bad.txt"), "compress a.bin b.cmp\nscan a.bin\n").unwrap();
+    let output = puyomdtool(&["batch", &path(&dir, "bad.txt")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad.txt line 2:"));
+}
+
+#[test]
+fn depfile_lists_every_file() {
+    let dir = temp_dir("cli-depfile");
+    std::fs::write(path(&dir, "title.bgword"), [0; 0x10]).unwrap();
+
+    let output = puyomdtool(&[
+        "convert",
+        "--depfile",
+        &path(&dir, "pal.d"),
+        &path(&dir, "title.bgword"),
+        &path(&dir, "title.bgpalm"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(path(&dir, "pal.d")).unwrap(),
+        format!(
+            "{} {}: \\\n  {}\n",
+            path(&dir, "title.bgpalm"),
+            path(&dir, "title.bgpalp"),
+            path(&dir, "title.bgword")
+        )
+    );
+
+    // The bgpalp file is read along with the bgpalm file.
+    let output = puyomdtool(&[
+        "--depfile",
+        &path(&dir, "word.d"),
+        "convert",
+        &path(&dir, "title.bgpalm"),
+        &path(&dir, "again.bgword"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(path(&dir, "word.d")).unwrap(),
+        format!(
+            "{}: \\\n  {} \\\n  {}\n",
+            path(&dir, "again.bgword"),
+            path(&dir, "title.bgpalm"),
+            path(&dir, "title.bgpalp")
+        )
+    );
+
+    // A rom fixed in place doesn't depend on itself.
+    std::fs::write(path(&dir, "rom.bin"), common::noise(0x400, 9)).unwrap();
+    let output = puyomdtool(&[
+        "fix",
+        &path(&dir, "rom.bin"),
+        "--depfile",
+        &path(&dir, "rom.d"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(path(&dir, "rom.d")).unwrap(),
+        format!("{}:\n", path(&dir, "rom.bin"))
+    );
+}
+
+#[test]
+fn cache_skips_unchanged_commands() {
+    let dir = temp_dir("cli-cache");
+    let (cache, input) = (path(&dir, "build.cache"), path(&dir, "art.bin"));
+    // Data from a few values, which --best compresses better than the default.
+    let art: Vec<u8> = common::noise(0x400, 7).iter().map(|b| b & 0x3).collect();
+    std::fs::write(&input, art).unwrap();
+
+    let output = path(&dir, "art.cmp");
+    let compress = |extra: &[&str]| {
+        let mut args = vec!["--cache", &cache, "compress"];
+        args.extend(extra);
+        args.extend([input.as_str(), output.as_str()]);
+        assert!(puyomdtool(&args).status.success());
+    };
+    compress(&[]);
+    let compressed = std::fs::read(path(&dir, "art.cmp")).unwrap();
+
+    // Changing the output by hand makes it run again.
+    std::fs::write(path(&dir, "art.cmp"), b"changed").unwrap();
+    compress(&[]);
+    assert_eq!(std::fs::read(path(&dir, "art.cmp")).unwrap(), compressed);
+
+    // Different options are a different command.
+    compress(&["--best"]);
+    assert_ne!(std::fs::read(path(&dir, "art.cmp")).unwrap(), compressed);
+    let text = std::fs::read_to_string(&cache).unwrap();
+    let commands = text.lines().filter(|line| line.starts_with("command "));
+    assert_eq!(commands.count(), 2);
+
+    // Nothing is rebuilt when nothing changed, whatever the modification times say.
+    let before = std::fs::metadata(path(&dir, "art.cmp"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    compress(&["--best"]);
+    let after = std::fs::metadata(path(&dir, "art.cmp"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(before, after);
+
+    // The order options are given in doesn't matter.
+    compress(&["--best", "--verify"]);
+    compress(&["--verify", "--best"]);
+    let text = std::fs::read_to_string(&cache).unwrap();
+    let commands = text.lines().filter(|line| line.starts_with("command "));
+    assert_eq!(commands.count(), 3);
+}
+
+#[test]
+fn cache_is_shared_between_processes() {
+    let dir = temp_dir("cli-cache-shared");
+    let cache = path(&dir, "build.cache");
+    for i in 0..16 {
+        std::fs::write(path(&dir, &format!("{i}.bin")), common::noise(0x400, i)).unwrap();
+    }
+
+    // Every command started at once keeps its entry.
+    let children: Vec<_> = (0..16)
+        .map(|i| {
+            Command::new(env!("CARGO_BIN_EXE_puyomdtool"))
+                .args(["--cache", &cache, "compress"])
+                .args([
+                    path(&dir, &format!("{i}.bin")),
+                    path(&dir, &format!("{i}.cmp")),
+                ])
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+    let text = std::fs::read_to_string(&cache).unwrap();
+    let commands = text.lines().filter(|line| line.starts_with("command "));
+    assert_eq!(commands.count(), 16);
+    assert!(text.contains(&format!("puyomdtool-{}", env!("CARGO_PKG_VERSION"))));
+}
+
+#[test]
+fn fix_check_mode() {
+    let dir = temp_dir("cli-fix-check");
+    let mut rom = common::noise(0x400, 21);
+    rom[0x18E..0x190].copy_from_slice(&[0, 0]);
+    std::fs::write(path(&dir, "rom.bin"), &rom).unwrap();
+
+    // A wrong checksum fails the check, and nothing is written.
+    let output = puyomdtool(&["fix", "--check", "--json", "-", &path(&dir, "rom.bin")]);
+    assert_eq!(output.status.code(), Some(4));
+    let calculated = puyomdtool::fix_checksum::checksum(&rom);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{{\"file\":\"{}\",\"stored\":0,\"calculated\":{calculated},\"correct\":false,\
+             \"written\":false}}\n",
+            path(&dir, "rom.bin")
+        )
+    );
+    assert_eq!(std::fs::read(path(&dir, "rom.bin")).unwrap(), rom);
+
+    assert!(
+        puyomdtool(&["fix", &path(&dir, "rom.bin"), &path(&dir, "fixed.bin")])
+            .status
+            .success()
+    );
+    let output = puyomdtool(&["fix", "--check", &path(&dir, "fixed.bin")]);
+    assert!(output.status.success());
+
+    // Fixing writes the output even when the checksum is already right.
+    assert!(
+        puyomdtool(&["fix", &path(&dir, "fixed.bin"), &path(&dir, "again.bin")])
+            .status
+            .success()
+    );
+    assert_eq!(
+        std::fs::read(path(&dir, "again.bin")).unwrap(),
+        std::fs::read(path(&dir, "fixed.bin")).unwrap()
+    );
+
+    let output = puyomdtool(&[
+        "fix",
+        "--check",
+        &path(&dir, "rom.bin"),
+        &path(&dir, "out.bin"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
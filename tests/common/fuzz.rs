@@ -0,0 +1,30 @@
+#![allow(dead_code)]
+
+// The checks the fuzz targets in fuzz/ run, shared so the tests can run them on fixed inputs.
+// The fuzz targets include this file directly.
+
+use puyomdtool::compress::{CompressMode, compress, compress_with_mode};
+use puyomdtool::decompress::{decompress, decompress_at_strict, trace};
+
+// Decompressing arbitrary data should never panic, and anything that does decode should decode
+// the same way after being recompressed.
+pub fn check_decompress(data: &[u8]) {
+    let _ = decompress_at_strict(data, 0, false);
+    let _ = trace(data, 0);
+    if let Ok(decompressed) = decompress(data, true) {
+        assert_eq!(
+            decompress(&compress(&decompressed), true).unwrap(),
+            decompressed
+        );
+    }
+}
+
+// Compressing arbitrary data should always round trip in both modes, passing the strict checks,
+// and optimal parsing should never lose to the greedy compressor.
+pub fn check_compress(data: &[u8]) {
+    let greedy = compress_with_mode(data, CompressMode::Greedy);
+    let best = compress_with_mode(data, CompressMode::Best);
+    assert_eq!(decompress_at_strict(&greedy, 0, true).unwrap().data, data);
+    assert_eq!(decompress_at_strict(&best, 0, true).unwrap().data, data);
+    assert!(best.len() <= greedy.len());
+}
@@ -0,0 +1,62 @@
+#![allow(dead_code)]
+
+pub mod fuzz;
+
+// Small xorshift generator so the tests don't need any extra dependencies.
+pub fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect()
+}
+
+pub fn hex(text: &str) -> Vec<u8> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// A mix of inputs that are easy and hard to compress.
+pub fn sample_inputs() -> Vec<Vec<u8>> {
+    let mut inputs = vec![
+        Vec::new(),
+        vec![0; 0x400],
+        vec![0xFF; 0x83],
+        (0..0x800).map(|i| (i % 7) as u8).collect(),
+        (0..0x800).map(|i| (i / 0x40) as u8).collect(),
+        (0..0x300).map(|i| i as u8).collect(),
+        include_bytes!("../../src/compress.rs").to_vec(),
+    ];
+    for seed in 1..8 {
+        // Noise with some repeated stretches mixed in.
+        let mut data = noise(0x200 * seed as usize, seed);
+        data.extend_from_within(0x40..0x140);
+        data.extend(noise(0x80, seed + 100));
+        data.extend_from_within(0..0x80);
+        inputs.push(data);
+    }
+    for seed in 1..32 {
+        // Short inputs from a small alphabet, which hit the edges of the run and cache commands.
+        let len = noise(1, seed)[0] as usize * 3;
+        inputs.push(noise(len, seed).iter().map(|b| b & 0x3).collect());
+    }
+    inputs
+}
+
+// A fresh, empty directory for tests that read and write files.
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("puyomdtool-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// The path of a file in dir, as a string for passing to the library or the tool.
+pub fn path(dir: &std::path::Path, name: &str) -> String {
+    dir.join(name).to_str().unwrap().to_string()
+}
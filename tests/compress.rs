@@ -0,0 +1,159 @@
+mod common;
+#[path = "../benches/reference/mod.rs"]
+mod reference;
+
+use common::fuzz::check_compress;
+use common::{hex, noise, sample_inputs};
+use puyomdtool::compress::{
+    CompressError, CompressMode, CompressOptions, UnalignedPolicy, compress, compress_with_mode,
+    compress_with_options,
+};
+use puyomdtool::decompress::decompress;
+
+// Expected output of the greedy compressor, taken from the original tool.
+const GOLDEN: &[(&str, &[u8], &str)] = &[
+    ("empty", b"", "00"),
+    ("single", b"\x42", "014200"),
+    ("zeros", &[0; 16], "01008c0000"),
+    ("abc_repeat", b"ABCABCABCABCABCD", "034142438902014400"),
+    (
+        "overlap",
+        b"\x01\x02\x01\x02\x01\x02\x01\x02\x01\x02\x01\x02",
+        "020102870100",
+    ),
+    (
+        "text",
+        b"puyo puyo puyo! compile puyo puyo tsu, puyo puyo sun",
+        "057075796f208604092120636f6d70696c65871205207473752c880e0373756e00",
+    ),
+];
+
+#[test]
+fn greedy_matches_golden_vectors() {
+    for (name, input, expected) in GOLDEN {
+        assert_eq!(compress(input), hex(expected), "{name}");
+    }
+}
+
+#[test]
+fn greedy_matches_golden_vectors_at_command_limits() {
+    // A run longer than 0x7F bytes, followed by a cache command that reaches back 0x100 bytes.
+    let mut far: Vec<u8> = (0..=0xFF).collect();
+    far.extend(0..8);
+    let mut expected = String::from("7f");
+    expected.extend((0x00..0x7F).map(|b| format!("{b:02x}")));
+    expected.push_str("7f");
+    expected.extend((0x7F..0xFE).map(|b| format!("{b:02x}")));
+    expected.push_str("02feff85ff00");
+    assert_eq!(compress(&far), hex(&expected));
+
+    // Caches have a maximum length of 0x82 bytes.
+    let mut two_runs = vec![0x11; 0x90];
+    two_runs.extend([0x22; 0x90]);
+    assert_eq!(compress(&two_runs), hex("0111ff008a820122ff008a8200"));
+}
+
+#[test]
+fn greedy_output_is_deterministic() {
+    for input in sample_inputs() {
+        assert_eq!(
+            compress(&input),
+            compress_with_mode(&input, CompressMode::Greedy)
+        );
+    }
+}
+
+#[test]
+fn round_trip() {
+    for input in sample_inputs() {
+        for mode in [CompressMode::Greedy, CompressMode::Best] {
+            let compressed = compress_with_mode(&input, mode);
+            assert_eq!(decompress(&compressed, true).unwrap(), input, "{mode:?}");
+
+            // With the 4-byte buffer, only whole groups of 4 bytes come back.
+            let aligned = &input[..input.len() & !0x3];
+            assert_eq!(decompress(&compressed, false).unwrap(), aligned, "{mode:?}");
+        }
+    }
+}
+
+#[test]
+fn round_trip_random_lengths() {
+    for seed in 1..200 {
+        let len = (seed as usize * 37) % 0x600;
+        let alphabet = [2, 4, 16, 0x100][seed as usize % 4];
+        let input: Vec<u8> = noise(len, seed)
+            .iter()
+            .map(|b| (*b as usize % alphabet) as u8)
+            .collect();
+        check_compress(&input);
+    }
+}
+
+#[test]
+fn greedy_matches_the_old_compressor() {
+    for input in sample_inputs() {
+        assert_eq!(compress(&input), reference::compress(&input));
+    }
+}
+
+#[test]
+fn cache_commands_reaching_the_end_of_the_data() {
+    // The shortest cache command, ending exactly at the end of the data.
+    assert_eq!(compress(b"ABCABC"), hex("03414243800200"));
+    // Cache commands at every length up to the limit, each one ending the data.
+    for len in 3..=0x90 {
+        let mut input = b"XYZ".to_vec();
+        input.extend((0..len).map(|i| b"XYZ"[i % 3]));
+        check_compress(&input);
+        assert!(compress(&input).iter().any(|byte| byte & 0x80 != 0));
+    }
+}
+
+#[test]
+fn best_is_never_larger_than_greedy() {
+    for input in sample_inputs() {
+        let greedy = compress(&input);
+        let best = compress_with_mode(&input, CompressMode::Best);
+        assert!(
+            best.len() <= greedy.len(),
+            "best mode produced 0x{:X} bytes, greedy produced 0x{:X}",
+            best.len(),
+            greedy.len()
+        );
+    }
+}
+
+#[test]
+fn verify_reports_unaligned_input() {
+    let options = CompressOptions::default();
+    let error = compress_with_options(&[1, 2, 3, 4, 5], &options).unwrap_err();
+    assert!(matches!(
+        error,
+        CompressError::VerifyFailed {
+            input_len: 5,
+            decompressed_len: 4,
+            unaligned_input: true,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn unaligned_policies() {
+    let refuse = CompressOptions {
+        unaligned: UnalignedPolicy::Refuse,
+        ..Default::default()
+    };
+    assert_eq!(
+        compress_with_options(&[1, 2, 3], &refuse),
+        Err(CompressError::UnalignedInput { input_len: 3 })
+    );
+
+    let pad = CompressOptions {
+        unaligned: UnalignedPolicy::Pad(0xFF),
+        ..Default::default()
+    };
+    let compressed = compress_with_options(&[1, 2, 3], &pad).unwrap();
+    assert_eq!(decompress(&compressed, false).unwrap(), [1, 2, 3, 0xFF]);
+}
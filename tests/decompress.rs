@@ -0,0 +1,196 @@
+mod common;
+
+use common::fuzz::check_decompress;
+use common::{hex, noise};
+use puyomdtool::compress::compress;
+use puyomdtool::decompress::{
+    Command, CommandKind, DecompressError, DecompressErrorKind, decompress, decompress_at,
+    decompress_at_limited, decompress_at_strict, trace,
+};
+use puyomdtool::scan::{ScanOptions, scan};
+
+#[test]
+fn arbitrary_input_does_not_panic() {
+    for seed in 1..2000 {
+        let len = seed as usize % 0x100;
+        check_decompress(&noise(len, seed));
+
+        // Mostly small run commands, which decode further before running out.
+        let small: Vec<u8> = noise(len, seed).iter().map(|b| b & 0x87).collect();
+        check_decompress(&small);
+    }
+}
+
+#[test]
+fn truncated_streams_return_errors() {
+    let error = |kind, offset, output_len| DecompressError {
+        kind,
+        offset,
+        output_len,
+    };
+
+    assert_eq!(
+        decompress(&[], false),
+        Err(error(DecompressErrorKind::MissingEndCommand, 0, 0))
+    );
+    assert_eq!(
+        decompress(&hex("0401020304"), false),
+        Err(error(DecompressErrorKind::MissingEndCommand, 5, 4))
+    );
+    assert_eq!(
+        decompress(&hex("050102"), false),
+        Err(error(DecompressErrorKind::TruncatedRun, 0, 0))
+    );
+    assert_eq!(
+        decompress(&hex("02010280"), true),
+        Err(error(DecompressErrorKind::MissingCacheOffset, 3, 2))
+    );
+}
+
+#[test]
+fn strict_mode_rejects_unwritten_cache_reads() {
+    // Reads 3 bytes starting 4 bytes back, when only 2 have been written.
+    let stream = hex("020102800300");
+    assert!(decompress(&stream, true).is_ok());
+    assert_eq!(
+        decompress_at_strict(&stream, 0, true).err().map(|e| e.kind),
+        Some(DecompressErrorKind::UninitializedCacheRead)
+    );
+}
+
+#[test]
+fn cache_reads_wrap_around_the_buffer() {
+    // 0x102 bytes counting up from 0, so the 0x100 byte buffer has wrapped and its write index
+    // is 2.  The cache command then reads 3 bytes starting 3 bytes back, from 0xFF through 0x01.
+    let mut stream = vec![0x7F];
+    stream.extend(0x00..=0x7E);
+    stream.push(0x7F);
+    stream.extend(0x7F..=0xFD);
+    stream.extend([0x04, 0xFE, 0xFF, 0x00, 0x01]);
+    stream.extend(hex("800200"));
+
+    let mut expected: Vec<u8> = (0..0x102).map(|i| i as u8).collect();
+    expected.extend([0xFF, 0x00, 0x01]);
+    assert_eq!(decompress(&stream, true).unwrap(), expected);
+    assert_eq!(
+        decompress_at_strict(&stream, 0, true).unwrap().data,
+        expected
+    );
+    assert_eq!(decompress(&compress(&expected), true).unwrap(), expected);
+}
+
+#[test]
+fn trace_lists_every_command() {
+    // Two bytes of padding, a run of "ABC", a cache command copying 4 bytes from 3 back, and the
+    // end command.
+    let data = hex(concat!("FFFF", "03414243", "8102", "00"));
+    let result = trace(&data, 2);
+    assert_eq!(result.error, None);
+    let command = |offset, kind, length, distance, output_start| Command {
+        offset,
+        kind,
+        length,
+        distance,
+        output_start,
+    };
+    assert_eq!(
+        result.commands,
+        [
+            command(2, CommandKind::Run, 3, None, 0),
+            command(6, CommandKind::Cache, 4, Some(3), 3),
+            command(8, CommandKind::End, 0, None, 7),
+        ]
+    );
+    let lines: Vec<String> = result.commands.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        lines,
+        [
+            "0x000002  run    len 0x03  dist -----  out 0x000000-0x000002",
+            "0x000006  cache  len 0x04  dist 0x003  out 0x000003-0x000006",
+            "0x000008  end    len 0x00  dist -----",
+        ]
+    );
+
+    // A stream cut off in its cache command keeps the commands before it and reports where it
+    // stopped.
+    let result = trace(&data[..7], 2);
+    assert_eq!(result.commands, [command(2, CommandKind::Run, 3, None, 0)]);
+    assert_eq!(
+        result.error,
+        Some(DecompressError {
+            kind: DecompressErrorKind::MissingCacheOffset,
+            offset: 6,
+            output_len: 3,
+        })
+    );
+}
+
+#[test]
+fn block_inside_larger_file() {
+    let mut rom = vec![0xFF; 0x10];
+    rom.extend(compress(&[7; 0x20]));
+    let block_len = rom.len() - 0x10;
+    rom.extend([0xEE; 0x10]);
+
+    let block = decompress_at(&rom, 0x10, false).unwrap();
+    assert_eq!(block.data, [7; 0x20]);
+    assert_eq!(block.compressed_len, block_len);
+    assert_eq!(block.discarded, 0);
+}
+
+#[test]
+fn strict_mode_rejects_split_runs() {
+    // A run is only cut short when a cache command or the end comes next.
+    let stream = hex("020102010300");
+    assert!(decompress(&stream, true).is_ok());
+    assert_eq!(
+        decompress_at_strict(&stream, 0, true).err().map(|e| e.kind),
+        Some(DecompressErrorKind::SplitRun)
+    );
+
+    // A full run can be followed by another.
+    let mut stream = vec![0x7F];
+    stream.extend([0xAA; 0x7F]);
+    stream.extend(hex("01BB00"));
+    assert!(decompress_at_strict(&stream, 0, true).is_ok());
+}
+
+#[test]
+fn limited_decoding_stops_at_the_limit() {
+    let stream = compress(&[0; 0x100]);
+    assert!(decompress_at_limited(&stream, 0, true, 0x100).is_ok());
+    assert_eq!(
+        decompress_at_limited(&stream, 0, true, 0xFF)
+            .err()
+            .map(|e| e.kind),
+        Some(DecompressErrorKind::TooLarge)
+    );
+}
+
+#[test]
+fn scan_finds_a_block_in_noise() {
+    // 0x10 tiles of something that compresses well, between two stretches of noise.
+    let art: Vec<u8> = (0..0x200).map(|i| ((i / 3) % 5 * 0x11) as u8).collect();
+    let block = compress(&art);
+    let mut rom = noise(0x2001, 7);
+    let offset = rom.len();
+    rom.extend(&block);
+    rom.extend(noise(0x2000, 8));
+
+    // Noise can happen to decode cleanly too, but nothing else should overlap the block.
+    let results = scan(&rom, &ScanOptions::default());
+    let found: Vec<(usize, usize, usize)> = results
+        .iter()
+        .filter(|result| {
+            result.offset < offset + block.len() && result.offset + result.compressed_len > offset
+        })
+        .map(|result| {
+            (
+                result.offset,
+                result.compressed_len,
+                result.decompressed_len,
+            )
+        })
+        .collect();
+    assert_eq!(found, [(offset, block.len(), art.len())]);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{path, temp_dir};
+use puyomdtool::PMDTError;
+use puyomdtool::convert::Convert;
+use puyomdtool::decompress::{Decompress, DecompressErrorKind};
+use puyomdtool::fix_checksum::FixChecksum;
+
+#[test]
+fn errors_name_the_file() {
+    let dir = temp_dir("errors-io");
+    let missing = dir.join("missing.bin");
+    let missing = missing.to_str().unwrap();
+    let output = dir.join("out.bin");
+
+    let error = Decompress::run(missing, output.to_str().unwrap(), false, false, None).unwrap_err();
+    assert!(matches!(&error, PMDTError::Io { path, .. } if path.to_str() == Some(missing)));
+    assert!(error.to_string().starts_with(missing));
+}
+
+#[test]
+fn errors_carry_details() {
+    let dir = temp_dir("errors-details");
+
+    std::fs::write(path(&dir, "broken.bin"), [0x05, 0x01, 0x02]).unwrap();
+    let error = Decompress::run(
+        &path(&dir, "broken.bin"),
+        &path(&dir, "out.bin"),
+        false,
+        false,
+        None,
+    );
+    assert!(matches!(
+        error,
+        Err(PMDTError::Decompress { source, .. })
+            if source.kind == DecompressErrorKind::TruncatedRun && source.offset == 0
+    ));
+
+    std::fs::write(path(&dir, "odd.bgword"), [0; 3]).unwrap();
+    let error = Convert::run(
+        &path(&dir, "odd.bgword"),
+        &path(&dir, "out.bgbyte"),
+        false,
+        false,
+        false,
+        0,
+    );
+    assert!(matches!(
+        error,
+        Err(PMDTError::WrongMappingSize {
+            expected: 4,
+            actual: 3,
+            ..
+        })
+    ));
+
+    std::fs::write(path(&dir, "even.bgword"), [0; 4]).unwrap();
+    let error = Convert::run(
+        &path(&dir, "even.bgword"),
+        &path(&dir, "out.map"),
+        false,
+        false,
+        false,
+        0,
+    );
+    assert!(
+        matches!(error, Err(PMDTError::UnknownMappingType { path }) if path.ends_with("out.map"))
+    );
+
+    std::fs::write(path(&dir, "tiny.md"), [0; 0x100]).unwrap();
+    let error = FixChecksum::run(&path(&dir, "tiny.md"), &path(&dir, "tiny.md"), None);
+    assert!(matches!(
+        error,
+        Err(PMDTError::InvalidRomSize {
+            size: 0x100,
+            min_size: 0x202,
+            ..
+        })
+    ));
+}
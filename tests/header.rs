@@ -0,0 +1,186 @@
+mod common;
+
+use common::path;
+use puyomdtool::PMDTError;
+use puyomdtool::fix_checksum::checksum;
+use puyomdtool::header::{EditHeader, Header, HeaderError, HeaderProblem, Sram, set_field};
+
+// A 0x400 byte rom with a valid header.
+fn sample_rom() -> Vec<u8> {
+    let mut rom = common::noise(0x400, 11);
+    let mut put =
+        |offset: usize, field: &[u8]| rom[offset..offset + field.len()].copy_from_slice(field);
+    put(0x100, b"SEGA MEGA DRIVE ");
+    put(0x110, b"(C)SEGA 1991.OCT");
+    put(0x120, &[b' '; 0x30]);
+    put(0x120, b"PUYO PUYO");
+    put(0x150, &[b' '; 0x30]);
+    put(0x150, b"PUYO PUYO");
+    put(0x180, b"GM 00001073-00");
+    put(0x190, b"J               ");
+    put(0x1A0, &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xFF]);
+    put(0x1A8, &[0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF]);
+    put(
+        0x1B0,
+        &[
+            b'R', b'A', 0xF8, 0x20, 0x00, 0x20, 0x00, 0x01, 0x00, 0x20, 0x3F, 0xFF,
+        ],
+    );
+    put(0x1BC, &[b' '; 0x0C]);
+    put(0x1F0, b"JUE             ");
+    let sum = checksum(&rom);
+    rom[0x18E..0x190].copy_from_slice(&sum.to_be_bytes());
+    rom
+}
+
+#[test]
+fn header_fields() {
+    let rom = sample_rom();
+    let header = Header::parse(&rom).unwrap();
+    assert_eq!(header.system, "SEGA MEGA DRIVE");
+    assert_eq!(header.copyright, "(C)SEGA 1991.OCT");
+    assert_eq!(header.domestic_name, "PUYO PUYO");
+    assert_eq!(header.serial, "GM 00001073-00");
+    assert_eq!(header.io_support, "J");
+    assert_eq!((header.rom_start, header.rom_end), (0, 0x3FF));
+    assert_eq!((header.ram_start, header.ram_end), (0xFF0000, 0xFFFFFF));
+    assert_eq!(
+        header.sram,
+        Some(Sram {
+            kind: 0xF8,
+            start: 0x200001,
+            end: 0x203FFF
+        })
+    );
+    assert_eq!(header.region, "JUE");
+    assert_eq!(header.problems(&rom), []);
+
+    assert_eq!(Header::parse(&rom[..0x1FF]), None);
+}
+
+#[test]
+fn header_problems() {
+    let mut rom = sample_rom();
+    rom[0x100..0x104].copy_from_slice(b"SEGO");
+    rom[0x1F0..0x1F3].copy_from_slice(b"JX ");
+    rom.extend([0; 0x10]);
+
+    let header = Header::parse(&rom).unwrap();
+    let problems = header.problems(&rom);
+    assert_eq!(
+        problems,
+        [
+            HeaderProblem::NotSega {
+                system: "SEGO MEGA DRIVE".into()
+            },
+            HeaderProblem::RomEnd {
+                end: 0x3FF,
+                expected: 0x40F
+            },
+            HeaderProblem::InvalidRegion {
+                region: "JX".into()
+            },
+        ]
+    );
+
+    // New style regions are a single hex digit.
+    rom[0x1F0..0x1F3].copy_from_slice(b"4  ");
+    rom[0x200] ^= 1;
+    let header = Header::parse(&rom).unwrap();
+    assert!(matches!(
+        header.problems(&rom)[..],
+        [
+            HeaderProblem::NotSega { .. },
+            HeaderProblem::RomEnd { .. },
+            HeaderProblem::Checksum { .. }
+        ]
+    ));
+}
+
+#[test]
+fn set_header_fields() {
+    let mut rom = sample_rom();
+    rom.extend(common::noise(0x100, 12));
+
+    set_field(&mut rom, "overseas-name", "PUYO PUYO TSU").unwrap();
+    set_field(&mut rom, "version", "01").unwrap();
+    let reserved = rom[0x1F3..0x200].to_vec();
+    set_field(&mut rom, "region", "U").unwrap();
+    assert_eq!(&rom[0x1F0..0x1F3], b"U  ");
+    assert_eq!(rom[0x1F3..0x200], reserved);
+    set_field(&mut rom, "rom-end", "auto").unwrap();
+    set_field(&mut rom, "ram-start", "$FF8000").unwrap();
+    assert_eq!(&rom[0x150..0x160], b"PUYO PUYO TSU   ");
+    assert_eq!(&rom[0x180..0x190 - 2], b"GM 00001073-01");
+
+    let header = Header::parse(&rom).unwrap();
+    assert_eq!(header.overseas_name, "PUYO PUYO TSU");
+    assert_eq!(header.region, "U");
+    assert_eq!(header.rom_end, 0x4FF);
+    assert_eq!(header.ram_start, 0xFF8000);
+
+    assert_eq!(
+        set_field(&mut rom, "serial", "GM 00001073-001"),
+        Err(HeaderError::TooLong {
+            field: "serial",
+            max: 0x0E
+        })
+    );
+    assert_eq!(
+        set_field(&mut rom, "region", "JUE4"),
+        Err(HeaderError::TooLong {
+            field: "region",
+            max: 0x03
+        })
+    );
+    assert_eq!(
+        set_field(&mut rom, "title", "PUYO"),
+        Err(HeaderError::UnknownField("title".into()))
+    );
+    assert!(matches!(
+        set_field(&mut rom, "rom-end", "big"),
+        Err(HeaderError::BadNumber { .. })
+    ));
+}
+
+#[test]
+fn set_header_fixes_checksum() {
+    let dir = common::temp_dir("header-edit");
+    let mut rom = sample_rom();
+    rom.extend(common::noise(0x100, 13));
+    std::fs::write(path(&dir, "rom.bin"), &rom).unwrap();
+
+    EditHeader::run(
+        &path(&dir, "rom.bin"),
+        &path(&dir, "out.bin"),
+        false,
+        &["rom-end=auto", "copyright=(C)COMPILE 1993"],
+    )
+    .unwrap();
+    let edited = std::fs::read(path(&dir, "out.bin")).unwrap();
+    assert_eq!(Header::parse(&edited).unwrap().problems(&edited), []);
+
+    let error = EditHeader::run(
+        &path(&dir, "rom.bin"),
+        &path(&dir, "out.bin"),
+        false,
+        &["modem"],
+    );
+    assert!(matches!(error, Err(PMDTError::InvalidArgument { .. })));
+    let error = EditHeader::run(
+        &path(&dir, "rom.bin"),
+        &path(&dir, "out.bin"),
+        false,
+        &["title=PUYO"],
+    );
+    assert!(matches!(
+        error,
+        Err(PMDTError::Header {
+            source: HeaderError::UnknownField(_),
+            ..
+        })
+    ));
+    let error = error.unwrap_err();
+    assert_eq!(error.exit_code(), 2);
+    assert!(std::error::Error::source(&error).is_some());
+}
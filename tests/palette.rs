@@ -0,0 +1,121 @@
+mod common;
+
+use common::{path, temp_dir};
+use puyomdtool::PMDTError;
+use puyomdtool::palette::{ColorMapping, Palette, PaletteError, PaletteFormat, load_colors};
+
+// Every 3-bit level in each channel, plus a few mixed colors.
+fn sample_palette() -> Palette {
+    let mut cram = Vec::new();
+    for level in 0..8u16 {
+        for shift in [1, 5, 9] {
+            cram.extend((level << shift).to_be_bytes());
+        }
+    }
+    cram.extend([0x0E, 0xEE, 0x02, 0x4C, 0x0A, 0x60]);
+    Palette::from_cram(&cram)
+}
+
+#[test]
+fn formats_round_trip() {
+    let palette = sample_palette();
+    for format in [
+        PaletteFormat::Cram,
+        PaletteFormat::Gpl,
+        PaletteFormat::Jasc,
+        PaletteFormat::Act,
+        PaletteFormat::Hex,
+    ] {
+        for mapping in [
+            ColorMapping::Linear,
+            ColorMapping::Shift,
+            ColorMapping::Hardware,
+        ] {
+            let data = palette.write(format, mapping).unwrap();
+            let read = Palette::read(&data, format, mapping).unwrap();
+            assert_eq!(read.to_cram(), palette.to_cram(), "{format:?} {mapping:?}");
+        }
+    }
+}
+
+#[test]
+fn color_mappings() {
+    let white = Palette::from_cram(&[0x0E, 0xEE]);
+    assert_eq!(white.to_rgb(), [[0xFF; 3]]);
+    assert_eq!(white.to_rgb_with(ColorMapping::Shift), [[0xE0; 3]]);
+
+    let levels: Vec<u8> = (0..8u16)
+        .map(|level| Palette::from_cram(&(level << 1).to_be_bytes()))
+        .map(|palette| palette.to_rgb_with(ColorMapping::Hardware)[0][0])
+        .collect();
+    assert_eq!(levels, [0, 52, 87, 116, 144, 172, 206, 255]);
+
+    // Colors that aren't exactly on a level are rounded to the nearest one.
+    let rounded = Palette::from_rgb(&[[0x30, 0x80, 0xF0]], ColorMapping::Linear);
+    assert_eq!(rounded.to_cram(), [0x0E, 0x82]);
+}
+
+#[test]
+fn reads_disassembly_hex() {
+    let text = b"; Title screen\n\tdc.w $0000, $0EEE, $0ACE ; white\n\tdc.w 0x0248\n";
+    let palette = Palette::read(text, PaletteFormat::Hex, ColorMapping::Linear).unwrap();
+    assert_eq!(
+        palette.to_cram(),
+        [0x00, 0x00, 0x0E, 0xEE, 0x0A, 0xCE, 0x02, 0x48]
+    );
+}
+
+#[test]
+fn rejects_bad_files() {
+    let read = |data: &[u8], format| Palette::read(data, format, ColorMapping::Linear).err();
+    assert_eq!(
+        read(b"JASC-PAL\n0100\n2\n0 0 0\n", PaletteFormat::Jasc),
+        Some(PaletteError::BadLine(5))
+    );
+    assert_eq!(
+        read(b"0 0 0\n", PaletteFormat::Gpl),
+        Some(PaletteError::MissingHeader("GIMP Palette"))
+    );
+    assert_eq!(
+        read(&[0; 0x30], PaletteFormat::Act),
+        Some(PaletteError::BadLength(0x30))
+    );
+}
+
+#[test]
+fn graphics_palettes_are_read_by_extension() {
+    let dir = temp_dir("palette-load");
+    let palette = sample_palette();
+    for (name, format) in [
+        ("pal.bin", PaletteFormat::Cram),
+        ("pal.gpl", PaletteFormat::Gpl),
+        ("pal.pal", PaletteFormat::Jasc),
+        ("pal.act", PaletteFormat::Act),
+        ("pal.hex", PaletteFormat::Hex),
+    ] {
+        let data = palette.write(format, ColorMapping::Linear).unwrap();
+        std::fs::write(path(&dir, name), data).unwrap();
+        let colors = load_colors(&[&path(&dir, name)], ColorMapping::Linear).unwrap();
+        assert_eq!(colors[..palette.len()], palette.to_rgb(), "{name}");
+    }
+
+    // Other extensions are raw CRAM, and files are joined in order.
+    std::fs::write(path(&dir, "pal.cram0"), palette.to_cram()).unwrap();
+    let colors = load_colors(
+        &[&path(&dir, "pal.gpl"), &path(&dir, "pal.cram0")],
+        ColorMapping::Linear,
+    )
+    .unwrap();
+    assert_eq!(colors.len(), palette.len() * 2);
+    assert_eq!(colors[palette.len()..], palette.to_rgb());
+
+    // A broken file is an error, rather than being read as CRAM.
+    std::fs::write(path(&dir, "broken.gpl"), b"not a palette").unwrap();
+    assert!(matches!(
+        load_colors(&[&path(&dir, "broken.gpl")], ColorMapping::Linear),
+        Err(PMDTError::Palette {
+            source: PaletteError::MissingHeader(_),
+            ..
+        })
+    ));
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use common::{hex, noise};
+use puyomdtool::import::{ImportError, image_to_indexes, import_tiles};
+use puyomdtool::palette::{ColorMapping, Palette};
+use puyomdtool::png::{Image, ImageData, PngError, decode, encode_indexed};
+use puyomdtool::render::render_tiles;
+use puyomdtool::tiles::{pixels_to_tiles, tiles_to_pixels};
+
+#[test]
+fn tiles_are_laid_out_in_rows() {
+    // Three tiles filled with colors 1, 2 and 3, two tiles to a row.
+    let mut tiles = vec![0x11; 0x20];
+    tiles.extend([0x22; 0x20]);
+    tiles.extend([0x33; 0x20]);
+
+    let (width, height, pixels) = tiles_to_pixels(&tiles, 2);
+    assert_eq!((width, height), (16, 16));
+    assert_eq!(pixels[7], 1);
+    assert_eq!(pixels[8], 2);
+    assert_eq!(pixels[8 * 16], 3);
+    assert_eq!(pixels[8 * 16 + 8], 0);
+}
+
+#[test]
+fn left_pixel_is_high_nibble() {
+    let mut tile = vec![0; 0x20];
+    tile[0] = 0x12;
+    tile[0x1F] = 0xEF;
+
+    let (_, _, pixels) = tiles_to_pixels(&tile, 1);
+    assert_eq!(&pixels[..2], &[1, 2]);
+    assert_eq!(&pixels[62..], &[0xE, 0xF]);
+}
+
+#[test]
+fn rendered_png_has_expected_header() {
+    let colors: Vec<[u8; 3]> = (0..0x10).map(|i| [i * 0x11; 3]).collect();
+    let png = render_tiles(&[0x11; 0x40], 1, &colors);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 8, 0, 0, 0, 16]);
+}
+
+#[test]
+fn pixels_to_tiles_undoes_tiles_to_pixels() {
+    let tiles = noise(0x20 * 12, 7);
+    let (width, height, pixels) = tiles_to_pixels(&tiles, 4);
+    assert_eq!(pixels_to_tiles(width, height, &pixels), tiles);
+}
+
+#[test]
+fn rendered_tiles_import_back() {
+    let colors: Vec<[u8; 3]> = (0..0x10).map(|i| [i * 0x11; 3]).collect();
+    let tiles = noise(0x20 * 6, 3);
+    let png = render_tiles(&tiles, 3, &colors);
+    assert_eq!(
+        import_tiles(&png, None, ColorMapping::Linear).unwrap(),
+        tiles
+    );
+}
+
+#[test]
+fn truecolor_pixels_match_the_nearest_level() {
+    // Colors 0-7 are gray levels 0-7.
+    let cram: Vec<u8> = (0..8u16)
+        .flat_map(|level| (level * 0x222).to_be_bytes())
+        .collect();
+    let palette = Palette::from_cram(&cram);
+    let image = |levels: &[u8]| Image {
+        width: levels.len(),
+        height: 1,
+        data: ImageData::Rgba(levels.iter().map(|&v| [v, v, v, 0xFF]).collect()),
+    };
+    let import = |levels: &[u8], mapping: ColorMapping| {
+        image_to_indexes(&image(levels), Some(&palette.to_rgb_with(mapping)), mapping)
+    };
+
+    // Hardware levels, and linear levels a few steps off after editing, both match.
+    let edited = [0x00, 0x26, 0x45, 0x70, 0x8E, 0xB9, 0xD7, 0xFC];
+    for levels in [ColorMapping::Hardware.levels(), edited] {
+        assert_eq!(
+            import(&levels, ColorMapping::Linear).unwrap(),
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        );
+    }
+
+    // Shift levels need their own mapping, or some land on the wrong level.
+    let shift = ColorMapping::Shift.levels();
+    assert_eq!(
+        import(&shift, ColorMapping::Shift).unwrap(),
+        [0, 1, 2, 3, 4, 5, 6, 7]
+    );
+    assert_eq!(
+        import(&shift, ColorMapping::Linear).unwrap(),
+        [0, 1, 2, 3, 4, 4, 5, 6]
+    );
+
+    // A pixel with no color at its level still fails, reporting the color in the image.
+    let colors = [[0; 3], [0x24; 3]];
+    assert!(matches!(
+        image_to_indexes(&image(&[0x26, 0xFC]), Some(&colors), ColorMapping::Linear),
+        Err(ImportError::ColorNotInPalette {
+            x: 1,
+            y: 0,
+            color: [0xFC, 0xFC, 0xFC]
+        })
+    ));
+}
+
+#[test]
+fn decodes_dynamic_huffman_png() {
+    // A 16x8 indexed image made by zlib at its highest compression level, using the Sub filter.
+    let png = hex(
+        "89504e470d0a1a0a0000000d4948445200000010000000080803000000c7a88fa500000006504c5445000000ffffffa5d99fdd000000494944415478da5dcc410a80301043d1fc99bad045bdff35dbb58d0541ac1042367948e21b36888be00a187373c4c8117ebad5ced92da7ec622b2dcadfd857a351e7af36bf1299ab71034b38263907dc99330000000049454e44ae426082",
+    );
+    let image = decode(&png).unwrap();
+    assert_eq!((image.width, image.height), (16, 8));
+    let ImageData::Indexed { palette, indexes } = image.data else {
+        panic!("expected an indexed image");
+    };
+    assert_eq!(palette, [[0, 0, 0], [0xFF, 0xFF, 0xFF]]);
+    let expected: Vec<u8> = (0..8)
+        .flat_map(|y| (0..16).map(move |x| ((x / 3 + y * 5 + (x * y) % 4) % 16) as u8))
+        .collect();
+    assert_eq!(indexes, expected);
+}
+
+#[test]
+fn rejects_bad_png_headers() {
+    let png = encode_indexed(8, 8, &[[0, 0, 0]], &[0; 0x40]);
+    // Changes the IHDR fields: width, height, bit depth and color type.
+    let with_header = |width: u32, height: u32, bit_depth: u8, color_type: u8| {
+        let mut png = png.clone();
+        png[16..20].copy_from_slice(&width.to_be_bytes());
+        png[20..24].copy_from_slice(&height.to_be_bytes());
+        png[24] = bit_depth;
+        png[25] = color_type;
+        decode(&png).map(|_| ())
+    };
+
+    assert_eq!(with_header(8, 8, 8, 3), Ok(()));
+    assert_eq!(
+        with_header(u32::MAX, u32::MAX, 16, 6),
+        Err(PngError::Corrupt("image is too large"))
+    );
+    assert_eq!(
+        with_header(0, 8, 8, 3),
+        Err(PngError::Corrupt("image has no pixels"))
+    );
+    assert_eq!(
+        with_header(8, 8, 4, 2),
+        Err(PngError::Corrupt("bad bit depth"))
+    );
+    assert_eq!(
+        with_header(8, 8, 16, 3),
+        Err(PngError::Corrupt("bad bit depth"))
+    );
+}
+
+#[test]
+fn background_applies_nametable_attributes() {
+    use puyomdtool::render_background::render_background;
+
+    // One tile with only its top left pixel set to color 5, placed four ways: plain, horizontally
+    // flipped on palette line 1, vertically flipped on line 2, and both on line 3.
+    let mut tiles = vec![0; 0x40];
+    tiles[0x20] = 0x50;
+    let words = [0x0101, 0x2901, 0x5101, 0x7901];
+    let colors = vec![[0; 3]; 0x40];
+
+    let image = decode(&render_background(&words, 2, &tiles, 0x100, &colors)).unwrap();
+    assert_eq!((image.width, image.height), (16, 16));
+    let ImageData::Indexed { indexes, .. } = image.data else {
+        panic!("expected an indexed image");
+    };
+    let set: Vec<(usize, usize, u8)> = indexes
+        .iter()
+        .enumerate()
+        .filter(|(_, index)| **index & 0xF != 0)
+        .map(|(i, index)| (i % 16, i / 16, *index))
+        .collect();
+    assert_eq!(
+        set,
+        [(0, 0, 0x05), (15, 0, 0x15), (0, 15, 0x25), (15, 15, 0x35)]
+    );
+}
+
+#[test]
+fn background_tiles_below_tile_base_are_blank() {
+    use puyomdtool::render_background::render_background;
+
+    let tiles = vec![0xFF; 0x40];
+    // Tile 0xFF is one below tile_base, and tile 0 is far below it.
+    let words = [0x00FF, 0x2000, 0x0100, 0x0101];
+    let colors = vec![[0; 3]; 0x40];
+
+    let image = decode(&render_background(&words, 4, &tiles, 0x100, &colors)).unwrap();
+    let ImageData::Indexed { indexes, .. } = image.data else {
+        panic!("expected an indexed image");
+    };
+    for (i, index) in indexes.iter().enumerate() {
+        let expected = match (i % 32) / 8 {
+            0 => 0x00,
+            1 => 0x10,
+            _ => 0x0F,
+        };
+        assert_eq!(*index, expected, "pixel {i}");
+    }
+}
+
+#[test]
+fn built_background_reuses_flipped_tiles() {
+    use puyomdtool::build_background::build_tilemap;
+    use puyomdtool::render_background::render_background;
+
+    // The same tile four ways, as in background_applies_nametable_attributes, followed by a copy
+    // of the first placement.
+    let mut tiles = vec![0; 0x40];
+    tiles[0x20] = 0x50;
+    let words = [0x0101, 0x2901, 0x5101, 0x7901, 0x0101, 0x0100];
+    let colors = vec![[0; 3]; 0x40];
+    let image = decode(&render_background(&words, 2, &tiles, 0x100, &colors)).unwrap();
+
+    let tilemap = build_tilemap(&image, None, ColorMapping::Linear, 0x100, true).unwrap();
+    assert_eq!(tilemap.tiles[..0x20], tiles[0x20..]);
+    assert_eq!(tilemap.tiles[0x20..], tiles[..0x20]);
+    assert_eq!(
+        tilemap.words,
+        [0x0100, 0x2900, 0x5100, 0x7900, 0x0100, 0x0101]
+    );
+
+    let tilemap = build_tilemap(&image, None, ColorMapping::Linear, 0, false).unwrap();
+    assert_eq!(tilemap.tiles.len(), 5 * 0x20);
+    assert_eq!(
+        tilemap.words,
+        [0x0000, 0x2001, 0x4002, 0x6003, 0x0000, 0x0004]
+    );
+}